@@ -0,0 +1,718 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    process,
+    sync::LazyLock,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::STATE_DIR,
+    error::ManifestError,
+    manifest::UnboxManifest,
+    utils::{atomic_write, generate_backup_file_name, move_path, unix_timestamp},
+};
+
+/// Name of the index file in a [`BackupRun`] directory.
+const RUN_INDEX_FILE_NAME: &str = "index.json";
+
+/// Matches backup file names made by [`generate_backup_file_name`], capturing the original file
+/// name and the generation.
+static BACKUP_FILE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?<name>.+)\.bak(?<generation>\d+)$").expect("backup file regex should compile")
+});
+
+/// Name of the [`BackupRun`] for this process (see [`__current_run_dir`]).
+static CURRENT_RUN_NAME: LazyLock<String> =
+    LazyLock::new(|| format!("{}-{}", unix_timestamp(), process::id()));
+
+/// Get the directory of the [`BackupRun`] for this process. It is only created when something is
+/// stashed in it (see [`stash`]).
+fn __current_run_dir() -> PathBuf {
+    BackupRun::runs_dir().join(&*CURRENT_RUN_NAME)
+}
+
+/// A backup of a file that was moved out of the way by unboxing.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Backup {
+    /// Where the backup is.
+    pub path: PathBuf,
+    /// Where the file was before it was backed up.
+    pub origin: PathBuf,
+    /// Generation of the backup. Higher generations are usually newer, but pruned generations
+    /// are reused. Backups in a [`BackupRun`] don't have a generation.
+    pub generation: Option<usize>,
+    /// When the backup was made, in seconds since the Unix epoch. If the backup isn't recorded in
+    /// an [`UnboxManifest`], this is when the file was last modified.
+    pub timestamp: u64,
+    /// Package that was unboxed over the original file, if it is known.
+    pub package: Option<PathBuf>,
+}
+
+impl Backup {
+    /// Parse a backup made by [`generate_backup_file_name`] from its path. Returns [`None`] if
+    /// `path` isn't named like a backup.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - Path of the backup.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_string_lossy();
+        let captures = BACKUP_FILE_REGEX.captures(&file_name)?;
+        let generation = Some(captures["generation"].parse().ok()?);
+        let origin = path.with_file_name(&captures["name"]);
+        let timestamp = path
+            .symlink_metadata()
+            .and_then(|md| md.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+
+        Some(Self {
+            path: path.to_path_buf(),
+            origin,
+            generation,
+            timestamp,
+            package: None,
+        })
+    }
+
+    /// Get the name of the [`BackupRun`] this backup is in, if it is in one.
+    #[must_use]
+    pub fn run_name(&self) -> Option<String> {
+        let run_tail = self.path.strip_prefix(BackupRun::runs_dir()).ok()?;
+        run_tail
+            .components()
+            .next()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+    }
+
+    /// Get how long ago this backup was made.
+    #[must_use]
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(unix_timestamp().saturating_sub(self.timestamp))
+    }
+
+    /// Restore this backup over whatever is at [`Backup::origin`]. A link at the origin (e.g. the
+    /// one that was unboxed there) is removed. Anything else is only replaced if `force` is
+    /// `true`, in which case it is stashed first (see [`stash`]) so it can be found and restored
+    /// again.
+    ///
+    /// # Arguments
+    ///
+    /// - `force` - Whether to replace files that aren't links.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the origin exists, isn't a link, and `force` is `false`, or if
+    /// anything cannot be moved, removed, or created.
+    pub fn restore(&self, force: bool) -> io::Result<()> {
+        let Self { path, origin, .. } = self;
+
+        match origin.symlink_metadata() {
+            Ok(md) if md.is_symlink() => fs::remove_file(origin)?,
+            Ok(_) if force => {
+                stash(origin, Path::new(""), self.package.as_deref())?;
+            }
+            Ok(_) => return Err(io::ErrorKind::AlreadyExists.into()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                // the origin's dir may have been removed when boxing up
+                if let Some(parent) = origin.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+            }
+            Err(err) => return Err(err),
+        }
+
+        move_path(path, origin)
+    }
+
+    /// Delete this backup.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the backup cannot be removed.
+    pub fn remove(&self) -> io::Result<()> {
+        let md = self.path.symlink_metadata()?;
+        if md.is_dir() {
+            fs::remove_dir_all(&self.path)
+        } else {
+            fs::remove_file(&self.path)
+        }
+    }
+}
+
+/// One file moved into a [`BackupRun`].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RunEntry {
+    /// Where the file was before it was moved.
+    pub origin: PathBuf,
+    /// Where the file is, relative to the run directory.
+    pub backup: PathBuf,
+    /// Package that was unboxed over the file, if it is known.
+    #[serde(default)]
+    pub package: Option<PathBuf>,
+    /// When the file was moved, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// A directory of files moved out of the way by one run of `unbox` (see
+/// [`crate::cli::ExistingFileStrategy::Stash`]). The files keep their paths relative to the
+/// target and are listed in an index file in the directory, so a whole run can be undone at once.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BackupRun {
+    /// Directory of the run
+    #[serde(skip)]
+    dir: PathBuf,
+    /// When the run started, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Files moved into the run.
+    pub entries: Vec<RunEntry>,
+}
+
+impl BackupRun {
+    /// Get the directory every [`BackupRun`] is kept in.
+    #[must_use]
+    pub fn runs_dir() -> PathBuf {
+        STATE_DIR.join("backups")
+    }
+
+    /// Read the run in `dir`. A directory without an index file is an empty run.
+    ///
+    /// # Arguments
+    ///
+    /// - `dir` - Directory of the run.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the index file cannot be read or parsed.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, ManifestError> {
+        let dir = dir.as_ref();
+        let index_path = dir.join(RUN_INDEX_FILE_NAME);
+        let mut run = match fs::read_to_string(&index_path) {
+            Ok(index_str) => serde_json::from_str::<Self>(&index_str)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self {
+                timestamp: unix_timestamp(),
+                ..Default::default()
+            },
+            Err(err) => {
+                return Err(ManifestError::Io {
+                    path: index_path,
+                    source: err,
+                });
+            }
+        };
+
+        run.dir = dir.to_path_buf();
+        Ok(run)
+    }
+
+    /// Read every run in [`BackupRun::runs_dir`], oldest first.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the runs directory or an index file cannot be read.
+    pub fn all() -> Result<Vec<Self>, ManifestError> {
+        let runs_dir = Self::runs_dir();
+        let entries = match fs::read_dir(&runs_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(ManifestError::Io {
+                    path: runs_dir,
+                    source: err,
+                });
+            }
+        };
+
+        let mut runs = Vec::new();
+        for res in entries {
+            let entry = res.map_err(|err| ManifestError::Io {
+                path: runs_dir.clone(),
+                source: err,
+            })?;
+            if entry.file_type().is_ok_and(|ft| ft.is_dir()) {
+                runs.push(Self::load(entry.path())?);
+            }
+        }
+
+        runs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then(a.dir.cmp(&b.dir)));
+        Ok(runs)
+    }
+
+    /// Get the directory of this run.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Get the name of this run, which is the name of its directory.
+    #[must_use]
+    pub fn name(&self) -> String {
+        self.dir
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().to_string())
+    }
+
+    /// Get the [`Backup`]s in this run that still exist.
+    #[must_use]
+    pub fn backups(&self) -> Vec<Backup> {
+        self.entries
+            .iter()
+            .map(|entry| Backup {
+                path: self.dir.join(&entry.backup),
+                origin: entry.origin.clone(),
+                generation: None,
+                timestamp: entry.timestamp,
+                package: entry.package.clone(),
+            })
+            .filter(|backup| backup.path.symlink_metadata().is_ok())
+            .collect()
+    }
+
+    /// Write the index file of this run. If the run has no entries left, the index file and any
+    /// empty directories left in the run are removed instead, which removes the run.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the index file cannot be written or removed.
+    pub fn save(&self) -> Result<(), ManifestError> {
+        let index_path = self.dir.join(RUN_INDEX_FILE_NAME);
+        if !self.entries.is_empty() {
+            let index_str = serde_json::to_string_pretty(self)?;
+            return atomic_write(&index_path, index_str).map_err(|err| ManifestError::Io {
+                path: index_path,
+                source: err,
+            });
+        }
+
+        match fs::remove_file(&index_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                return Err(ManifestError::Io {
+                    path: index_path,
+                    source: err,
+                });
+            }
+            _ => {}
+        }
+        // anything that isn't empty wasn't put here by us, so leave it alone
+        for entry in walkdir::WalkDir::new(&self.dir)
+            .contents_first(true)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_dir())
+        {
+            let _ = fs::remove_dir(entry.path());
+        }
+
+        Ok(())
+    }
+}
+
+/// Move `path` into the [`BackupRun`] of this process, keeping its path relative to `target`,
+/// and add it to the run's index. Paths outside of `target` keep their full path in the run.
+/// Returns where `path` was moved to.
+///
+/// # Arguments
+///
+/// - `path` - Path to move.
+/// - `target` - Target `path` is in.
+/// - `package` - Package being unboxed over `path`, if it is known.
+///
+/// # Errors
+///
+/// An error is returned if `path` cannot be moved or the run's index cannot be updated.
+///
+/// # Panics
+///
+/// This function will panic if the backup isn't in the run directory, which shouldn't happen.
+pub fn stash(path: &Path, target: &Path, package: Option<&Path>) -> io::Result<PathBuf> {
+    let relative_path = match path.strip_prefix(target) {
+        Ok(tail) if !target.as_os_str().is_empty() && !tail.as_os_str().is_empty() => {
+            tail.to_path_buf()
+        }
+        _ => path
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect(),
+    };
+
+    let run_dir = &__current_run_dir();
+    let mut backup = run_dir.join(&relative_path);
+    // stashing the same path twice (e.g. from two targets) shouldn't overwrite the first one
+    if backup.symlink_metadata().is_ok() {
+        backup = generate_backup_file_name(backup);
+    }
+    if let Some(parent) = backup.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    move_path(path, &backup)?;
+
+    let mut run = BackupRun::load(run_dir).map_err(io::Error::other)?;
+    run.entries.push(RunEntry {
+        origin: path.to_path_buf(),
+        backup: backup
+            .strip_prefix(run_dir)
+            .expect("backup should be in the run dir")
+            .to_path_buf(),
+        package: package.map(Path::to_path_buf),
+        timestamp: unix_timestamp(),
+    });
+    run.save().map_err(io::Error::other)?;
+
+    Ok(backup)
+}
+
+/// Read every backup recorded in the state registry (see [`UnboxManifest::all_registered`]).
+/// Recorded backups that no longer exist are skipped.
+///
+/// # Errors
+///
+/// An error is returned if the state registry cannot be read.
+pub fn recorded_backups() -> Result<Vec<Backup>, ManifestError> {
+    let backups = UnboxManifest::all_registered()?
+        .iter()
+        .flat_map(|manifest| {
+            manifest.records.iter().filter_map(|record| {
+                let path = record.backup.as_ref()?;
+                let mut backup = Backup::from_path(path)?;
+                backup.origin.clone_from(&record.dest);
+                backup.timestamp = record.timestamp;
+                backup.package = Some(manifest.package.clone());
+                Some(backup)
+            })
+        })
+        .filter(|backup| backup.path.symlink_metadata().is_ok())
+        .collect();
+
+    Ok(backups)
+}
+
+/// Collect the backups of files in `dir` that boxunbox recorded: the ones in an
+/// [`UnboxManifest`] (see [`recorded_backups`]) and the ones in every [`BackupRun`]. Files that
+/// are only named like backups (e.g. `foo.bak1`) are never collected since they may not be ours.
+/// Backups are sorted by origin, then age.
+///
+/// # Arguments
+///
+/// - `dir` - Directory the backed up files were in, usually a target.
+///
+/// # Errors
+///
+/// An error is returned if the state registry cannot be read.
+pub fn collect_backups<P: AsRef<Path>>(dir: P) -> Result<Vec<Backup>, ManifestError> {
+    let dir = dir.as_ref();
+    let mut backups = HashMap::new();
+    for backup in recorded_backups()? {
+        if backup.origin.starts_with(dir) {
+            backups.insert(backup.path.clone(), backup);
+        }
+    }
+    for run in BackupRun::all()? {
+        for backup in run.backups() {
+            if backup.origin.starts_with(dir) {
+                backups.insert(backup.path.clone(), backup);
+            }
+        }
+    }
+
+    let mut backups = backups.into_values().collect::<Vec<_>>();
+    backups.sort_by(|a, b| {
+        a.origin
+            .cmp(&b.origin)
+            .then(a.timestamp.cmp(&b.timestamp))
+            .then(a.generation.cmp(&b.generation))
+    });
+    Ok(backups)
+}
+
+/// Select the backups to prune. Backups are grouped by origin and, in each group, the `keep`
+/// newest backups are kept and any backup older than `older_than` is pruned. If both are
+/// given, a backup is pruned if either says so.
+///
+/// # Arguments
+///
+/// - `backups` - Backups to select from.
+/// - `keep` - How many backups of each origin to keep.
+/// - `older_than` - Minimum age of a pruned backup.
+#[must_use]
+pub fn select_prunable(
+    backups: &[Backup],
+    keep: Option<usize>,
+    older_than: Option<Duration>,
+) -> Vec<Backup> {
+    let mut by_origin = HashMap::<&Path, Vec<&Backup>>::new();
+    for backup in backups {
+        by_origin.entry(&backup.origin).or_default().push(backup);
+    }
+
+    let mut prunable = Vec::new();
+    for mut group in by_origin.into_values() {
+        // newest first
+        group.sort_by_key(|b| std::cmp::Reverse((b.timestamp, b.generation)));
+        for (i, backup) in group.into_iter().enumerate() {
+            let is_extra = keep.is_some_and(|keep| i >= keep);
+            let is_old = older_than.is_some_and(|older_than| backup.age() > older_than);
+            if is_extra || is_old {
+                prunable.push(backup.clone());
+            }
+        }
+    }
+
+    prunable.sort_by(|a, b| a.path.cmp(&b.path));
+    prunable
+}
+
+/// Remove restored or pruned backups from every [`UnboxManifest`] in the state registry that
+/// records them, so boxing up doesn't try to restore them, and from every [`BackupRun`] that
+/// lists them. Runs with no backups left are removed.
+///
+/// # Arguments
+///
+/// - `backup_paths` - Paths of the backups to forget.
+///
+/// # Errors
+///
+/// An error is returned if a manifest or run index cannot be read or saved.
+pub fn forget_backups<P: AsRef<Path>>(backup_paths: &[P]) -> Result<(), ManifestError> {
+    let is_forgotten = |path: &Path| backup_paths.iter().any(|p| p.as_ref() == path);
+
+    for mut manifest in UnboxManifest::all_registered()? {
+        let mut changed = false;
+        for record in &mut manifest.records {
+            if record.backup.as_deref().is_some_and(is_forgotten) {
+                record.backup = None;
+                changed = true;
+            }
+        }
+
+        if changed {
+            manifest.save()?;
+        }
+    }
+
+    for mut run in BackupRun::all()? {
+        let entry_count = run.entries.len();
+        let run_dir = run.dir.clone();
+        run.entries
+            .retain(|entry| !is_forgotten(&run_dir.join(&entry.backup)));
+        if run.entries.len() != entry_count {
+            run.save()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Format a [`Duration`] as a short, human readable age (e.g. `3d`).
+///
+/// # Arguments
+///
+/// - `age` - Age to format.
+#[must_use]
+pub fn format_age(age: Duration) -> String {
+    const UNITS: [(u64, &str); 4] = [
+        (7 * 24 * 3600, "w"),
+        (24 * 3600, "d"),
+        (3600, "h"),
+        (60, "m"),
+    ];
+
+    let secs = age.as_secs();
+    UNITS
+        .iter()
+        .find(|(unit_secs, _)| secs >= *unit_secs)
+        .map_or_else(
+            || format!("{secs}s"),
+            |(unit_secs, unit)| format!("{}{unit}", secs / unit_secs),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use crate::{
+        manifest::LinkOutcome,
+        package::{LinkType, PackageConfig},
+        plan::PlannedLink,
+    };
+
+    use super::*;
+
+    /// Create `count` backups of `origin`, oldest first, each one day older than the next.
+    fn make_backups(origin: &Path, count: usize) -> anyhow::Result<Vec<Backup>> {
+        (0..count)
+            .map(|i| {
+                let path = generate_backup_file_name(origin);
+                fs::write(&path, format!("generation {i}"))
+                    .with_context(|| format!("failed to write test backup {path:?}"))?;
+                let mut backup = Backup::from_path(&path).context("failed to parse test backup")?;
+                let days_old = u64::try_from(count - i).context("test backup count is too big")?;
+                backup.timestamp = unix_timestamp() - days_old * 24 * 3600;
+                Ok(backup)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_from_path() {
+        let backup = Backup::from_path(Path::new("/home/user/.bashrc.bak12"))
+            .expect("backup should be parsed");
+        assert_eq!(backup.origin, Path::new("/home/user/.bashrc"));
+        assert_eq!(backup.generation, Some(12));
+
+        assert!(Backup::from_path(Path::new("/home/user/.bashrc")).is_none());
+        assert!(Backup::from_path(Path::new("/home/user/.bak0")).is_none());
+    }
+
+    #[test]
+    fn test_restore() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let origin = target.path().join(".bashrc");
+        let backups = make_backups(&origin, 2)?;
+
+        // a real file is only replaced with force
+        fs::write(&origin, "current").context("failed to write test file")?;
+        let err = backups[0].restore(false).expect_err("restored over a file");
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        backups[0]
+            .restore(true)
+            .context("failed to restore backup")?;
+        assert_eq!(fs::read_to_string(&origin)?, "generation 0");
+        // the replaced file was stashed, so it can be found again
+        let stashed = collect_backups(target.path())
+            .context("failed to collect backups")?
+            .into_iter()
+            .find(|b| b.origin == origin)
+            .context("replaced file should be stashed")?;
+        assert_eq!(fs::read_to_string(&stashed.path)?, "current");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stash() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let origin = target_path.join(".config/foo/config.toml");
+        fs::create_dir_all(
+            origin
+                .parent()
+                .context("test origin should have a parent")?,
+        )
+        .context("failed to create test origin parent")?;
+        fs::write(&origin, "original").context("failed to write test origin")?;
+
+        let backup_path = stash(&origin, target_path, None).context("failed to stash file")?;
+        assert!(!origin.exists(), "{origin:?} was not moved");
+        // the run mirrors the target
+        assert_eq!(
+            backup_path,
+            __current_run_dir().join(".config/foo/config.toml")
+        );
+        assert_eq!(fs::read_to_string(&backup_path)?, "original");
+
+        let backup = collect_backups(target_path)
+            .context("failed to collect backups")?
+            .into_iter()
+            .find(|b| b.path == backup_path)
+            .context("stashed file should be collected")?;
+        assert_eq!(backup.origin, origin);
+        assert_eq!(backup.generation, None);
+        assert_eq!(
+            backup.run_name(),
+            __current_run_dir()
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        );
+
+        backup
+            .restore(false)
+            .context("failed to restore stashed file")?;
+        forget_backups(&[&backup_path]).context("failed to forget stashed file")?;
+        assert_eq!(fs::read_to_string(&origin)?, "original");
+        let run = BackupRun::load(__current_run_dir()).context("failed to load run")?;
+        assert!(
+            run.entries.iter().all(|e| e.origin != origin),
+            "restored file is still in the run index"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_backups_recorded_only() -> anyhow::Result<()> {
+        let package = tempfile::tempdir().context("failed to create temp package")?;
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let mut config = PackageConfig::new(package.path());
+        config.target = target.path().to_path_buf();
+
+        let origin = target.path().join(".bashrc");
+        let recorded = make_backups(&origin, 1)?;
+        let mut manifest = UnboxManifest::new(&config);
+        manifest.record(
+            &PlannedLink::new(
+                package.path().join(".bashrc"),
+                origin,
+                LinkType::SymlinkAbsolute,
+            ),
+            LinkOutcome::Moved,
+            Some(recorded[0].path.clone()),
+        );
+        manifest.save().context("failed to save manifest")?;
+        // named like a backup, but boxunbox didn't make it
+        let unrecorded = make_backups(&target.path().join("foo"), 2)?;
+
+        let collected = collect_backups(target.path()).context("failed to collect backups")?;
+        assert_eq!(
+            collected.iter().map(|b| &b.path).collect::<Vec<_>>(),
+            [&recorded[0].path]
+        );
+        let prunable = select_prunable(&collected, Some(0), None);
+        assert!(
+            prunable.iter().all(|b| b.path != unrecorded[1].path),
+            "unrecorded backup would be pruned"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_prunable() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let bashrc_backups = make_backups(&target.path().join(".bashrc"), 3)?;
+        let zshrc_backups = make_backups(&target.path().join(".zshrc"), 1)?;
+        let backups = bashrc_backups
+            .iter()
+            .chain(&zshrc_backups)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let by_count = select_prunable(&backups, Some(1), None);
+        assert_eq!(by_count, bashrc_backups[..2]);
+
+        // zshrc's only backup is 1 day old, bashrc's are 1, 2, and 3 days old
+        let by_age = select_prunable(&backups, None, Some(Duration::from_hours(36)));
+        assert_eq!(by_age, bashrc_backups[..2]);
+
+        let by_both = select_prunable(&backups, Some(2), Some(Duration::from_hours(60)));
+        assert_eq!(by_both, bashrc_backups[..1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(Duration::from_secs(5)), "5s");
+        assert_eq!(format_age(Duration::from_secs(90)), "1m");
+        assert_eq!(format_age(Duration::from_hours(72)), "3d");
+        assert_eq!(format_age(Duration::from_hours(15 * 24)), "2w");
+    }
+}
@@ -1,45 +1,132 @@
 use std::{
-    collections::HashSet,
-    fs::{self, OpenOptions},
-    io::{self, BufRead, BufReader},
+    fs,
     path::{Path, PathBuf},
 };
 
 use anyhow::Context;
 use boxunbox::{
+    backup::forget_backups,
+    boxup::{BoxUpPlan, restore_backup},
     cli::{BoxUpCli, ColorOverride},
-    error::UnboxError,
+    deps::find_unboxed_dependents,
+    hooks::{HookStage, run_hooks},
+    manifest::UnboxManifest,
+    package::PackageConfig,
+    plan::PlannedLink,
+    prompt::{TerminalPrompt, confirm},
     utils::replace_home_with_tilde,
 };
 use clap::Parser;
 use colored::Colorize;
 
-// FIXME: TESTING
-
-fn get_unboxed_paths(last_unboxing_file: impl AsRef<Path>) -> Result<Vec<PathBuf>, UnboxError> {
-    let last_unboxing_file = last_unboxing_file.as_ref();
-    let lufd = OpenOptions::new()
-        .create(false)
-        .read(true)
-        .open(last_unboxing_file)
-        .map_err(|err| UnboxError::Io {
-            path: last_unboxing_file.to_path_buf(),
-            source: err,
-        })?;
-    let bufreader = BufReader::new(lufd);
-    // read each line into a HashSet to deduplicate
-    let paths_set = bufreader
-        .lines()
-        .map(|res| {
-            res.map(PathBuf::from).map_err(|err| UnboxError::Io {
-                path: last_unboxing_file.to_path_buf(),
-                source: err,
-            })
-        })
-        .collect::<Result<HashSet<_>, _>>()?;
-    let mut paths_vec = Vec::from_iter(paths_set);
-    paths_vec.sort();
-    Ok(paths_vec)
+/// Returns `true` if `a` and `b` point to the same path. The paths are compared as-is and, if they
+/// both exist, canonicalized.
+///
+/// # Arguments
+///
+/// - `a` - First path.
+/// - `b` - Second path.
+fn is_same_path(a: &Path, b: &Path) -> bool {
+    a == b
+        || matches!(
+            (dunce::canonicalize(a), dunce::canonicalize(b)),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
+
+/// Remove the links in a [`BoxUpPlan`].
+///
+/// # Arguments
+///
+/// - `plan` - Plan to execute.
+/// - `fail_fast` - Whether to return on the first error or not.
+///
+/// # Errors
+///
+/// An error is returned if a link cannot be removed and `fail_fast` is `true`. Otherwise, the
+/// paths of the links that were removed are returned.
+fn box_up_links(plan: &BoxUpPlan, fail_fast: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let mut removed_dests = Vec::with_capacity(plan.links().len());
+
+    for pl in plan.links() {
+        let path = pl.dest();
+        match pl
+            .boxup()
+            .with_context(|| format!("failed to remove unboxed link: {path:?}"))
+        {
+            Ok(()) => {
+                println!(
+                    "successfully removed {}",
+                    replace_home_with_tilde(path).red()
+                );
+                removed_dests.push(path.to_path_buf());
+            }
+            Err(err) if fail_fast => return Err(err),
+            Err(err) => eprintln!(
+                "{}: failed to remove {}: {err:#}",
+                "warn".yellow(),
+                replace_home_with_tilde(path)
+            ),
+        }
+    }
+
+    Ok(removed_dests)
+}
+
+/// Restore the backups of the files that were moved when the removed links were unboxed, unless
+/// the plan keeps them, and report how many were restored and kept.
+///
+/// # Arguments
+///
+/// - `plan` - Executed plan.
+/// - `removed_dests` - Paths of the links that were removed.
+fn restore_backups(plan: &BoxUpPlan, removed_dests: &[PathBuf]) {
+    let mut restored_paths = Vec::new();
+    let mut kept_count = 0_usize;
+
+    for dest in removed_dests {
+        let Some(backup) = plan.backup_for(dest) else {
+            continue;
+        };
+
+        if !plan.restores_backups() {
+            kept_count += 1;
+            continue;
+        }
+
+        match restore_backup(dest, backup) {
+            Ok(()) => {
+                println!(
+                    "restored {} -> {}",
+                    replace_home_with_tilde(backup).yellow(),
+                    replace_home_with_tilde(dest).cyan()
+                );
+                restored_paths.push(backup.to_path_buf());
+            }
+            Err(err) => {
+                eprintln!(
+                    "{}: failed to restore {}: {err}",
+                    "warn".yellow(),
+                    replace_home_with_tilde(backup)
+                );
+                kept_count += 1;
+            }
+        }
+    }
+
+    if !restored_paths.is_empty() {
+        println!("restored {} backup(s)", restored_paths.len());
+        // restored backups may be listed in a backup run
+        if let Err(err) = forget_backups(&restored_paths) {
+            eprintln!(
+                "{}: failed to update backup records: {err}",
+                "warn".yellow()
+            );
+        }
+    }
+    if kept_count > 0 {
+        println!("kept {kept_count} backup(s)");
+    }
 }
 
 fn main() -> anyhow::Result<()> {
@@ -48,13 +135,14 @@ fn main() -> anyhow::Result<()> {
     #[cfg(debug_assertions)]
     println!("cli={cli:#?}");
 
-    // TODO: include/exclude patterns
-    // TODO: dry run
     let BoxUpCli {
+        dry_run,
         fail_fast,
-        packages,
+        force,
+        ref packages,
         color_override,
         keep_last_file,
+        ref target,
         ..
     } = cli;
 
@@ -64,50 +152,218 @@ fn main() -> anyhow::Result<()> {
         ColorOverride::Never => colored::control::set_override(false),
     }
 
-    for package in packages {
-        let canon_package = dunce::canonicalize(package)?;
-        let last_unboxing_file = canon_package.join(".bub.last");
-        let last_unboxed_paths = get_unboxed_paths(&last_unboxing_file)
-            .context("failed to read list of unboxed paths")?;
+    let packages = packages
+        .iter()
+        .map(dunce::canonicalize)
+        .collect::<Result<Vec<_>, _>>()?;
 
-        #[cfg(debug_assertions)]
-        println!("unboxed paths: {last_unboxed_paths:#?}");
+    // check every package before boxing any of them up so the run isn't left half done
+    let mut required_packages = Vec::new();
+    for canon_package in &packages {
+        // packages being boxed up together don't count as dependents
+        let dependents = find_unboxed_dependents(canon_package)
+            .context("failed to find dependent packages")?
+            .into_iter()
+            .filter(|dependent| !packages.contains(dependent))
+            .collect::<Vec<_>>();
+        if dependents.is_empty() {
+            continue;
+        }
 
-        for path in &last_unboxed_paths {
-            if path.is_dir() {
-                continue;
-            } else {
-                match fs::remove_file(path)
-                    .with_context(|| format!("failed to remove unboxed file: {path:?}"))
-                {
-                    Ok(()) => {}
-                    Err(err) if fail_fast => return Err(err),
-                    Err(err) => eprintln!(
-                        "{}: failed to remove {}: {err}",
-                        "warn".yellow(),
-                        path.display()
-                    ),
-                }
-            }
-            println!(
-                "successfully removed {}",
-                replace_home_with_tilde(path).red()
+        let dependents_str = dependents
+            .iter()
+            .map(replace_home_with_tilde)
+            .collect::<Vec<_>>()
+            .join(", ");
+        if force {
+            eprintln!(
+                "{}: boxing up {} even though it is required by {dependents_str}",
+                "warn".yellow(),
+                replace_home_with_tilde(canon_package)
             );
+        } else {
+            required_packages.push(format!(
+                "{} is required by unboxed package(s) {dependents_str}",
+                replace_home_with_tilde(canon_package)
+            ));
         }
-
-        println!(
-            "successfully boxed up {}",
-            replace_home_with_tilde(canon_package).red()
+    }
+    if !required_packages.is_empty() {
+        let problem = format!(
+            "{}; box them up first or use --force",
+            required_packages.join("; ")
         );
+        if dry_run {
+            eprintln!("{}: {problem}", "warn".yellow());
+        } else {
+            anyhow::bail!(problem);
+        }
+    }
+
+    for canon_package in &packages {
+        let config =
+            PackageConfig::load(canon_package, None).context("failed to read package config")?;
+        let manifests = if cli.scan {
+            Vec::new()
+        } else {
+            UnboxManifest::load_all(&config)
+                .context("failed to read unbox manifests")?
+                .into_iter()
+                .filter(|m| target.as_ref().is_none_or(|t| is_same_path(t, &m.target)))
+                .collect::<Vec<_>>()
+        };
 
-        let luf_string = replace_home_with_tilde(&last_unboxing_file);
+        let jobs = if manifests.is_empty() {
+            let mut config = config.clone();
+            if let Some(target) = target {
+                config.target.clone_from(target);
+            }
+            if !cli.scan {
+                eprintln!(
+                    "{}: {} has no unbox manifest, scanning {} for links into it",
+                    "warn".yellow(),
+                    replace_home_with_tilde(canon_package),
+                    replace_home_with_tilde(&config.target)
+                );
+            }
 
-        if keep_last_file {
-            println!("keeping {}", luf_string.cyan());
+            let plan = BoxUpPlan::scan(&config).context("failed to scan for unboxed links")?;
+            // any manifest for this target is stale now
+            let stale_manifests = UnboxManifest::load_all(&config)
+                .context("failed to read unbox manifests")?
+                .into_iter()
+                .filter(|m| is_same_path(&config.target, &m.target))
+                .collect::<Vec<_>>();
+            vec![(plan, stale_manifests, true)]
         } else {
-            fs::remove_file(&last_unboxing_file)
-                .with_context(|| format!("failed to remove {last_unboxing_file:?}"))?;
-            println!("removed last file list {}", luf_string.red());
+            manifests
+                .into_iter()
+                .map(|m| (BoxUpPlan::from_manifest(&m), vec![m], false))
+                .collect()
+        };
+
+        for (mut plan, manifests, is_scanned) in jobs {
+            plan.filter(&cli.include_pats, &cli.exclude_pats);
+            plan.set_restore_backups(!cli.no_restore);
+            for pl in plan
+                .links()
+                .iter()
+                .filter(|pl| !pl.is_owned_by(canon_package))
+            {
+                let action = if force {
+                    "removing it anyway"
+                } else {
+                    "skipping it (use --force to remove it anyway)"
+                };
+                eprintln!(
+                    "{}: {} no longer links into {}, {action}",
+                    "warn".yellow(),
+                    replace_home_with_tilde(pl.dest()),
+                    replace_home_with_tilde(canon_package)
+                );
+            }
+            if !force {
+                plan.remove_unowned();
+            }
+
+            if plan.is_empty() {
+                eprintln!(
+                    "{}: nothing to box up from {}",
+                    "warn".yellow(),
+                    replace_home_with_tilde(plan.target())
+                );
+                continue;
+            }
+
+            println!("{}", plan.display());
+
+            // hooks see the target the links were unboxed to, which may not be the configured one
+            let mut config = config.clone();
+            config.target = plan.target().to_path_buf();
+            let last_unboxed_paths = plan
+                .links()
+                .iter()
+                .map(PlannedLink::dest)
+                .collect::<Vec<_>>();
+
+            if dry_run {
+                eprintln!("dry run, not executing");
+                run_hooks(HookStage::PreBoxup, &config, &last_unboxed_paths, dry_run)?;
+                run_hooks(HookStage::PostBoxup, &config, &last_unboxed_paths, dry_run)?;
+                continue;
+            }
+
+            // links found by scanning are not necessarily ours, so let the user review them
+            if is_scanned
+                && !cli.yes
+                && !confirm(&mut TerminalPrompt, "Box up these links?")
+                    .context("failed to confirm, use --yes to skip it")?
+            {
+                println!("not boxing up {}", replace_home_with_tilde(canon_package));
+                continue;
+            }
+
+            run_hooks(HookStage::PreBoxup, &config, &last_unboxed_paths, false)?;
+
+            let removed_dests = box_up_links(&plan, fail_fast)?;
+            let failed_count = plan.links().len() - removed_dests.len();
+
+            if failed_count == 0 {
+                println!(
+                    "successfully boxed up {} from {}",
+                    replace_home_with_tilde(canon_package).red(),
+                    replace_home_with_tilde(plan.target()).cyan()
+                );
+            } else {
+                eprintln!(
+                    "{}: {failed_count} link(s) could not be removed from {}",
+                    "warn".yellow(),
+                    replace_home_with_tilde(plan.target())
+                );
+            }
+
+            restore_backups(&plan, &removed_dests);
+
+            for mut manifest in manifests {
+                let manifest_string = replace_home_with_tilde(manifest.path());
+                // keep the records of links that weren't removed (i.e. filtered or failed) so they
+                // can still be boxed up
+                manifest
+                    .records
+                    .retain(|r| !removed_dests.contains(&r.dest));
+
+                let other_manifests =
+                    UnboxManifest::all_registered().context("failed to read unbox manifests")?;
+                for dir in manifest.unshared_dirs(&other_manifests) {
+                    // only empty dirs can be removed, anything else is left alone
+                    if fs::remove_dir(&dir).is_ok() {
+                        println!(
+                            "removed empty directory {}",
+                            replace_home_with_tilde(&dir).red()
+                        );
+                    }
+                }
+                manifest.created_dirs.retain(|dir| dir.exists());
+
+                if !manifest.records.is_empty() {
+                    manifest.save().context("failed to save unbox manifest")?;
+                    println!(
+                        "kept {} record(s) of links that weren't removed in {}",
+                        manifest.records.len(),
+                        manifest_string.cyan()
+                    );
+                } else if keep_last_file {
+                    println!("keeping {}", manifest_string.cyan());
+                } else {
+                    manifest
+                        .remove()
+                        .context("failed to remove unbox manifest")?;
+                    println!("removed unbox manifest {}", manifest_string.red());
+                }
+            }
+
+            // the manifests are up to date first so a failing hook can't leave stale records
+            run_hooks(HookStage::PostBoxup, &config, &last_unboxed_paths, false)?;
         }
     }
 
@@ -0,0 +1,515 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use colored::Colorize;
+use regex::Regex;
+
+use crate::{
+    cli::UnboxCli,
+    error::PlanningError,
+    manifest::UnboxManifest,
+    package::{HardLinkFallback, LinkType, PackageConfig},
+    plan::{PlannedLink, UnboxPlan},
+    utils::{move_path, replace_home_with_tilde},
+};
+
+/// Helper struct for printing a [`BoxUpPlan`] with [`Display`]. Created by [`BoxUpPlan::display`].
+pub struct DisplayBoxUpPlan<'a> {
+    plan: &'a BoxUpPlan,
+}
+
+/// A plan for boxing up (part of) a package, i.e. the links to remove from one target.
+#[derive(Debug)]
+pub struct BoxUpPlan {
+    /// Package the links point into
+    package: PathBuf,
+    /// Target the links are in
+    target: PathBuf,
+    /// Links to remove, sorted by [`PlannedLink::dest`]
+    links: Vec<PlannedLink>,
+    /// Backups of files that were moved when unboxing, by [`PlannedLink::dest`]
+    backups: HashMap<PathBuf, PathBuf>,
+    /// Whether to restore [`BoxUpPlan::backups`] or not
+    restore_backups: bool,
+}
+
+impl Display for DisplayBoxUpPlan<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let BoxUpPlan {
+            package,
+            target,
+            links,
+            backups,
+            restore_backups,
+        } = self.plan;
+
+        writeln!(f, "Here's the boxing plan:")?;
+        writeln!(
+            f,
+            "Package: {}",
+            replace_home_with_tilde(package).bright_green()
+        )?;
+        writeln!(f, "Target: {}", replace_home_with_tilde(target).cyan())?;
+
+        // links to the package or target itself (i.e. `link_root`) would be stripped to an empty
+        // string, so they're printed in full
+        let path_formatter = |p: &Path, prefix: &Path| match p.strip_prefix(prefix) {
+            Ok(stripped) if !stripped.as_os_str().is_empty() => {
+                stripped.to_string_lossy().to_string()
+            }
+            _ => replace_home_with_tilde(p),
+        };
+
+        for pl in links {
+            let formatted_dest = path_formatter(pl.dest(), target);
+            let formatted_src = path_formatter(pl.src(), package);
+
+            if matches!(pl.ty(), LinkType::HardLink | LinkType::Copy) {
+                write!(
+                    f,
+                    "{} ({}) -> {}",
+                    formatted_dest.cyan(),
+                    pl.ty().to_string().bright_red(),
+                    formatted_src.bright_green(),
+                )?;
+            } else {
+                write!(
+                    f,
+                    "{} -> {}",
+                    formatted_dest.cyan(),
+                    formatted_src.bright_green(),
+                )?;
+            }
+
+            match backups.get(pl.dest()) {
+                Some(backup) if *restore_backups => writeln!(
+                    f,
+                    " (restoring {})",
+                    path_formatter(backup, target).yellow()
+                )?,
+                _ => writeln!(f)?,
+            }
+        }
+
+        let links_noun = if links.len() == 1 { "link" } else { "links" };
+        write!(
+            f,
+            "{} {links_noun} will be {}",
+            links.len(),
+            "removed".bright_red()
+        )?;
+
+        let backup_count = links
+            .iter()
+            .filter(|pl| backups.contains_key(pl.dest()))
+            .count();
+        if backup_count > 0 {
+            let backups_noun = if backup_count == 1 {
+                "backup"
+            } else {
+                "backups"
+            };
+            let backups_verb = if *restore_backups {
+                "restored".yellow()
+            } else {
+                "kept".cyan()
+            };
+            write!(f, ", {backup_count} {backups_noun} will be {backups_verb}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl BoxUpPlan {
+    /// Create a [`BoxUpPlan`] for `links`.
+    ///
+    /// # Arguments
+    ///
+    /// - `package` - Package the links point into.
+    /// - `target` - Target the links are in.
+    /// - `links` - Links to remove.
+    fn new(package: PathBuf, target: PathBuf, mut links: Vec<PlannedLink>) -> Self {
+        links.sort_by(|a, b| a.dest().cmp(b.dest()));
+        Self {
+            package,
+            target,
+            links,
+            backups: HashMap::new(),
+            restore_backups: true,
+        }
+    }
+
+    /// Plan boxing up every link recorded in an [`UnboxManifest`].
+    ///
+    /// # Arguments
+    ///
+    /// - `manifest` - Manifest to read links from.
+    #[must_use]
+    pub fn from_manifest(manifest: &UnboxManifest) -> Self {
+        let mut plan = Self::new(
+            manifest.package.clone(),
+            manifest.target.clone(),
+            manifest.records.iter().map(PlannedLink::from).collect(),
+        );
+        plan.backups = manifest
+            .records
+            .iter()
+            .filter_map(|r| Some((r.dest.clone(), r.backup.clone()?)))
+            .collect();
+
+        plan
+    }
+
+    /// Plan boxing up a package without an [`UnboxManifest`]. The unboxing of the package is
+    /// re-planned with [`UnboxPlan::plan_unboxing`] and every planned link that currently links
+    /// into the package (see [`PlannedLink::is_owned_by`]) is included. This finds links even if
+    /// the manifest was lost or is stale (e.g. because unboxing failed partway).
+    ///
+    /// # Arguments
+    ///
+    /// - `config` - Config of the package to box up.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if [`UnboxPlan::plan_unboxing`] fails for any reason other than the
+    /// package being empty.
+    pub fn scan(config: &PackageConfig) -> Result<Self, PlanningError> {
+        let PackageConfig {
+            package, target, ..
+        } = config;
+
+        // the config's target is used as-is, nested configs use their own. Hard links that can't
+        // exist across filesystems are planned as copies, which also finds links that were
+        // symlinked instead since ownership is checked by what's actually at the dest.
+        let mut cli = UnboxCli::new(package);
+        cli.hard_link_fallback = Some(HardLinkFallback::Copy);
+        let mut config = config.clone();
+        config.hard_link_fallback = HardLinkFallback::Copy;
+        let planned_links = match UnboxPlan::plan_unboxing(config, &cli) {
+            Ok(plan) => plan.links().to_vec(),
+            Err(PlanningError::EmptyPlan) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        let mut links = Vec::<PlannedLink>::new();
+        for pl in planned_links {
+            // a parent dir may have been linked instead (e.g. a folded dir or `link_root` from the
+            // CLI), in which case that link is the one to remove
+            let Some(owned) = Self::__find_dir_link(&pl, package, target)
+                .or_else(|| pl.is_owned_by(package).then_some(pl))
+            else {
+                continue;
+            };
+
+            if !links.iter().any(|l| l.dest() == owned.dest()) {
+                links.push(owned);
+            }
+        }
+
+        Ok(Self::new(package.clone(), target.clone(), links))
+    }
+
+    /// Utility function for [`BoxUpPlan::scan`] that finds the closest ancestor of
+    /// [`PlannedLink::dest`] in `target` (including `target` itself) that is a symlink into
+    /// `package`, and returns it as a [`PlannedLink`] to the matching ancestor of
+    /// [`PlannedLink::src`].
+    ///
+    /// # Arguments
+    ///
+    /// - `pl` - Planned link to check the ancestors of.
+    /// - `package` - Package the ancestor must link into.
+    /// - `target` - Target directory to stop at.
+    fn __find_dir_link(pl: &PlannedLink, package: &Path, target: &Path) -> Option<PlannedLink> {
+        pl.dest()
+            .ancestors()
+            .skip(1)
+            .take_while(|dest_dir| dest_dir.starts_with(target))
+            .filter(|dest_dir| dest_dir.is_symlink())
+            .find_map(|dest_dir| {
+                let depth = pl.dest().strip_prefix(dest_dir).ok()?.components().count();
+                let src_dir = pl.src().ancestors().nth(depth)?;
+                let ty = if fs::read_link(dest_dir).ok()?.is_absolute() {
+                    LinkType::SymlinkAbsolute
+                } else {
+                    LinkType::SymlinkRelative
+                };
+
+                let dir_link = PlannedLink::new(src_dir, dest_dir, ty);
+                dir_link.is_owned_by(package).then_some(dir_link)
+            })
+    }
+
+    /// Filter the links in this plan by their path in the package, like
+    /// [`UnboxPlan::plan_unboxing`] does. A link is kept if any component of its package-relative
+    /// source path matches an include pattern (or there are none), unless any component matches
+    /// an exclude pattern.
+    ///
+    /// # Arguments
+    ///
+    /// - `include_pats` - Patterns to include.
+    /// - `exclude_pats` - Patterns to exclude, checked after `include_pats`.
+    pub fn filter(&mut self, include_pats: &[Regex], exclude_pats: &[Regex]) {
+        let package = &self.package;
+        self.links.retain(|pl| {
+            let tail = pl.src().strip_prefix(package).unwrap_or(pl.src());
+            let components = if tail.as_os_str().is_empty() {
+                // `link_root` links point to the package itself
+                package.file_name().into_iter().collect::<Vec<_>>()
+            } else {
+                tail.iter().collect()
+            };
+            let matches_any = |pats: &[Regex]| {
+                pats.iter()
+                    .any(|re| components.iter().any(|c| re.is_match(&c.to_string_lossy())))
+            };
+
+            (include_pats.is_empty() || matches_any(include_pats)) && !matches_any(exclude_pats)
+        });
+    }
+
+    /// Remove the links that no longer link into the package from this plan (see
+    /// [`PlannedLink::is_owned_by`]) and return them.
+    pub fn remove_unowned(&mut self) -> Vec<PlannedLink> {
+        let (owned, unowned) = self
+            .links
+            .drain(..)
+            .partition(|pl| pl.is_owned_by(&self.package));
+        self.links = owned;
+        unowned
+    }
+
+    /// Set whether backups of files that were moved when unboxing are restored or not. They are
+    /// restored by default.
+    ///
+    /// # Arguments
+    ///
+    /// - `restore_backups` - Whether to restore backups or not.
+    pub fn set_restore_backups(&mut self, restore_backups: bool) {
+        self.restore_backups = restore_backups;
+    }
+
+    /// Returns `true` if backups are restored (see [`BoxUpPlan::set_restore_backups`]).
+    #[must_use]
+    pub fn restores_backups(&self) -> bool {
+        self.restore_backups
+    }
+
+    /// Get the backup made when the file at `dest` was moved by unboxing, if there is one.
+    ///
+    /// # Arguments
+    ///
+    /// - `dest` - Link path.
+    #[must_use]
+    pub fn backup_for(&self, dest: &Path) -> Option<&Path> {
+        self.backups.get(dest).map(PathBuf::as_path)
+    }
+
+    /// Get the package the links in this plan point into.
+    #[must_use]
+    pub fn package(&self) -> &Path {
+        &self.package
+    }
+
+    /// Get the target the links in this plan are in.
+    #[must_use]
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// Get the [`PlannedLink`]s in this plan, sorted by [`PlannedLink::dest`].
+    #[must_use]
+    pub fn links(&self) -> &[PlannedLink] {
+        &self.links
+    }
+
+    /// Returns `true` if there is nothing to box up.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Returns an object implementing [`Display`] for printing this [`BoxUpPlan`]. This is
+    /// modeled after [`UnboxPlan::display`].
+    #[must_use]
+    pub fn display(&self) -> DisplayBoxUpPlan<'_> {
+        DisplayBoxUpPlan { plan: self }
+    }
+}
+
+/// Restore a backup made when a file was moved out of the way by unboxing (see
+/// [`crate::cli::ExistingFileStrategy::Move`] and [`crate::cli::ExistingFileStrategy::Stash`]) by
+/// moving it back to where it was.
+///
+/// # Arguments
+///
+/// - `dest` - Where the file was, which is where the link was.
+/// - `backup` - Where the file was moved to.
+///
+/// # Errors
+///
+/// An error is returned if something already exists at `dest` (e.g. the link wasn't removed) or
+/// the backup cannot be moved.
+pub fn restore_backup(dest: &Path, backup: &Path) -> io::Result<()> {
+    if dest.symlink_metadata().is_ok() {
+        return Err(io::ErrorKind::AlreadyExists.into());
+    }
+
+    move_path(backup, dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use crate::{
+        manifest::LinkOutcome,
+        test_utils::{TEST_PACKAGE_FILE_TAILS, TEST_TARGET, make_tmp_tree_with_target},
+        utils::os_symlink,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_scan() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+        let config =
+            PackageConfig::try_from_package(package_path).context("failed to read config")?;
+
+        // only some of the package is unboxed, and one link was replaced by the user
+        let unboxed = PlannedLink::new(
+            package_path.join("test.txt"),
+            target_path.join("test.txt"),
+            LinkType::SymlinkAbsolute,
+        );
+        unboxed.unbox(true).context("failed to unbox test link")?;
+        let nested = PlannedLink::new(
+            package_path.join("folder1/nested1.txt"),
+            target_path.join("folder1/nested1.txt"),
+            LinkType::SymlinkAbsolute,
+        );
+        nested
+            .unbox(true)
+            .context("failed to unbox nested test link")?;
+        fs::write(target_path.join("test_ignore.txt"), "not a link")
+            .context("failed to write test file")?;
+
+        let plan = BoxUpPlan::scan(&config).context("failed to scan target")?;
+        assert_eq!(plan.links(), [nested, unboxed]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_through_dir_link() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+        let config =
+            PackageConfig::try_from_package(package_path).context("failed to read config")?;
+
+        // folder1 was linked as a whole, so its files are only reachable through the dir link
+        os_symlink(package_path.join("folder1"), target_path.join("folder1"))
+            .context("failed to create test dir link")?;
+
+        // only the dir link should be removed, never the package files behind it
+        let plan = BoxUpPlan::scan(&config).context("failed to scan target")?;
+        let expected = PlannedLink::new(
+            package_path.join("folder1"),
+            target_path.join("folder1"),
+            LinkType::SymlinkAbsolute,
+        );
+        assert_eq!(plan.links(), [expected]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter() {
+        let package = PathBuf::from(TEST_TARGET).join("package");
+        let target = PathBuf::from(TEST_TARGET);
+        let links = TEST_PACKAGE_FILE_TAILS
+            .iter()
+            .map(|tail| {
+                PlannedLink::new(
+                    package.join(tail),
+                    target.join(tail),
+                    LinkType::SymlinkAbsolute,
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut plan = BoxUpPlan::new(package.clone(), target.clone(), links);
+
+        let include_pats = [Regex::new("^folder").expect("test regex should compile")];
+        let exclude_pats = [Regex::new("^test_ignore").expect("test regex should compile")];
+        plan.filter(&include_pats, &exclude_pats);
+
+        let actual = plan
+            .links()
+            .iter()
+            .map(|pl| pl.dest().to_path_buf())
+            .collect::<Vec<_>>();
+        let expected = [
+            "folder1/nested1.txt",
+            "folder2/nested2 again.txt",
+            "folder2/nested2.txt",
+        ]
+        .into_iter()
+        .map(|tail| target.join(tail))
+        .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_restore_backup() -> anyhow::Result<()> {
+        const EXISTING_TARGET_FILE_CONTENTS: &str = "i already exist";
+
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+        let config =
+            PackageConfig::try_from_package(package_path).context("failed to read config")?;
+
+        let pl = PlannedLink::new(
+            package_path.join("test.txt"),
+            target_path.join("test.txt"),
+            LinkType::SymlinkAbsolute,
+        );
+        fs::write(pl.dest(), EXISTING_TARGET_FILE_CONTENTS)
+            .context("failed to write existing target file")?;
+        let backup = target_path.join("test.txt.bak0");
+        fs::rename(pl.dest(), &backup).context("failed to move existing target file")?;
+        pl.unbox(true).context("failed to unbox test link")?;
+
+        let mut manifest = UnboxManifest::new(&config);
+        manifest.record(&pl, LinkOutcome::Moved, Some(backup.clone()));
+        let plan = BoxUpPlan::from_manifest(&manifest);
+        assert_eq!(plan.backup_for(pl.dest()), Some(backup.as_path()));
+
+        // the link is still there, so the backup can't be restored yet
+        let err = restore_backup(pl.dest(), &backup).expect_err("restored over a link");
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        pl.boxup().context("failed to box up test link")?;
+        restore_backup(pl.dest(), &backup).context("failed to restore backup")?;
+        assert!(!backup.exists(), "backup was not moved");
+        assert_eq!(
+            fs::read_to_string(pl.dest()).context("failed to read restored file")?,
+            EXISTING_TARGET_FILE_CONTENTS
+        );
+
+        Ok(())
+    }
+}
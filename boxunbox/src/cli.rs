@@ -1,16 +1,21 @@
 use std::{
     fmt::{self, Display},
     path::PathBuf,
+    time::Duration,
 };
 
 use anyhow::Context;
 use clap::{
-    Parser, ValueEnum, ValueHint,
-    builder::{Styles, styling::AnsiColor},
+    Args, Parser, Subcommand, ValueEnum, ValueHint,
+    builder::{PossibleValuesParser, Styles, TypedValueParser, styling::AnsiColor},
 };
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-use crate::{package::LinkType, utils::expand_into_pathbuf};
+use crate::{
+    package::{HardLinkFallback, LinkType},
+    utils::expand_into_pathbuf,
+};
 
 /// Get the color styles for the CLI help menu.
 fn __cli_styles() -> Styles {
@@ -35,39 +40,246 @@ fn cli_parse_pathbuf(s: &str) -> Result<PathBuf, String> {
         .map_err(|err| err.to_string())
 }
 
+/// Parses a `&str` slice as a [`PathBuf`] like [`cli_parse_pathbuf`], but makes it absolute
+/// without canonicalizing it. This is for paths that may not exist or may be symlinks that should
+/// not be followed.
+///
+/// # Arguments
+///
+/// - `s` - `&str` slice.
+fn cli_parse_pathbuf_lenient(s: &str) -> Result<PathBuf, String> {
+    expand_into_pathbuf(s)
+        .and_then(|p| {
+            std::path::absolute(&p)
+                .with_context(|| format!("failed to make {} absolute", p.display()))
+        })
+        .map(path_clean::clean)
+        .map_err(|err| err.to_string())
+}
+
+/// Parses a `&str` slice as a [`Duration`]. The format is a whole number followed by a unit:
+/// `s` (seconds), `m` (minutes), `h` (hours), `d` (days), or `w` (weeks), e.g. `30d`.
+///
+/// # Arguments
+///
+/// - `s` - `&str` slice.
+fn cli_parse_duration(s: &str) -> Result<Duration, String> {
+    let unit_start = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in '{s}', expected one of s, m, h, d, or w"))?;
+    let (count, unit) = s.split_at(unit_start);
+    let count = count
+        .parse::<u64>()
+        .map_err(|err| format!("invalid number in '{s}': {err}"))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "unknown unit '{unit}', expected one of s, m, h, d, or w"
+            ));
+        }
+    };
+
+    Ok(Duration::from_secs(count * unit_secs))
+}
+
+/// Get the parser for `--ask-fallback`, which accepts every [`ExistingFileStrategy`] except
+/// [`ExistingFileStrategy::Ask`], since falling back to asking would never end.
+fn cli_ask_fallback_parser() -> impl TypedValueParser<Value = ExistingFileStrategy> {
+    PossibleValuesParser::new(
+        ExistingFileStrategy::value_variants()
+            .iter()
+            .filter(|efs| !matches!(efs, ExistingFileStrategy::Ask))
+            .filter_map(ValueEnum::to_possible_value),
+    )
+    .map(|s| ExistingFileStrategy::from_str(&s, false).expect("possible values should parse"))
+}
+
 /// Override the color setting. Default is [`ColorOverride::Auto`].
-#[derive(Copy, Clone, Debug, ValueEnum)]
+#[derive(Copy, Clone, Debug, Default, ValueEnum)]
 pub enum ColorOverride {
     /// Always display color (i.e. force it).
     Always,
     /// Automatically determine if color should be used or not.
+    #[default]
     Auto,
     /// Never display color.
     Never,
 }
 
 /// Describes what to do if a target link already exists.
-#[derive(Copy, Clone, Debug, ValueEnum)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, ValueEnum)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
+#[serde(rename_all = "lowercase")]
 pub enum ExistingFileStrategy {
+    /// Ask what to do about each target file, falling back to `--ask-fallback` if stdin is not a
+    /// terminal.
+    Ask,
     /// "Adopt" the target file by overwriting the packages file with the target file and
-    /// placing a symlink in the target. (destructive!)
+    /// placing a symlink in the target. Directories are merged into the package. Overwritten
+    /// package files are backed up first unless they're committed to git, and uncommitted changes
+    /// are refused.
     Adopt,
     /// Ignore the link and continue.
     Ignore,
     /// Move the target file to `<target>.bak0` (or `<target>.bak1`, etc.) and create a symlink at
     /// the original target.
     Move,
+    /// Move the target file into a backup directory for this run in the state directory,
+    /// keeping its path relative to the target, and create a symlink at the original target.
+    Stash,
     /// Overwrite the target file with the package file. (destructive!)
     Overwrite,
+    /// Move the target file to the trash, where it can be recovered with desktop tools, and
+    /// create a symlink at the original target.
+    Trash,
     /// Throw an error.
+    #[default]
+    #[serde(rename = "error")]
     #[value(name = "error")]
     ThrowError,
 }
 
+/// Options for discovering packages in a directory of packages.
+#[derive(Clone, Debug, Default, Args)]
+pub struct DiscoveryArgs {
+    /// Directory of packages to discover. Every child directory with a package config is
+    /// considered a package.
+    #[arg(short = 'D', long, value_parser = cli_parse_pathbuf, value_hint = ValueHint::DirPath)]
+    pub stow_dir: Option<PathBuf>,
+    /// Consider every child directory of `--stow-dir` a package, like GNU stow, even if it has
+    /// no package config.
+    #[arg(long, requires = "stow_dir")]
+    pub stow_mode: bool,
+    /// Select discovered packages by name with a regex. May be specified multiple times.
+    ///
+    /// When no select pattern is specified, every discovered package is selected.
+    #[arg(
+        short = 'S',
+        long = "select",
+        value_name = "REGEX",
+        requires = "stow_dir"
+    )]
+    pub select_pats: Vec<Regex>,
+}
+
+/// List discovered packages and their status.
+#[derive(Clone, Debug, Args)]
+pub struct ListCli {
+    /// Packages (directories) to list. If neither packages nor `--stow-dir` are specified, the
+    /// current directory is searched for packages.
+    #[arg(value_parser = cli_parse_pathbuf, value_hint = ValueHint::DirPath)]
+    pub packages: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub discovery: DiscoveryArgs,
+}
+
+/// Move existing files into a package and link them back, creating the package if needed.
+///
+/// Global flags such as `--target`, `--link-type`, and `--exclude` apply to the package. Its
+/// config is saved if it doesn't have one yet.
+#[derive(Clone, Debug, Args)]
+pub struct ImportCli {
+    /// Package (directory) to import into. It is created if it doesn't exist.
+    #[arg(value_parser = cli_parse_pathbuf_lenient, value_hint = ValueHint::DirPath)]
+    pub package: PathBuf,
+    /// Files or directories in the target to import, e.g. `~/.zshrc` or `~/.config/nvim`.
+    #[arg(required = true, value_parser = cli_parse_pathbuf_lenient, value_hint = ValueHint::AnyPath)]
+    pub paths: Vec<PathBuf>,
+    /// Dry run; show what would be imported, but do not import it.
+    #[arg(short = 'd', long)]
+    pub dry_run: bool,
+}
+
+/// Options for finding backups made by unboxing.
+#[derive(Clone, Debug, Args)]
+pub struct BackupSearchArgs {
+    /// Only use backups of files in this directory. Only backups recorded when unboxing (i.e.
+    /// with `--if-exists move` or `stash`) are used, never other files named like backups.
+    /// [default: ~]
+    #[arg(short, long, value_parser = cli_parse_pathbuf, value_hint = ValueHint::DirPath)]
+    pub target: Option<PathBuf>,
+}
+
+/// List backups with their origin and age.
+#[derive(Clone, Debug, Args)]
+pub struct BackupListCli {
+    #[command(flatten)]
+    pub search: BackupSearchArgs,
+}
+
+/// Restore a backup to where it came from, replacing the link there.
+#[derive(Clone, Debug, Args)]
+#[command(group = clap::ArgGroup::new("restore_what").required(true).multiple(true))]
+pub struct BackupRestoreCli {
+    /// Original path of the backed up file (e.g. `~/.bashrc`, not `~/.bashrc.bak0`).
+    #[arg(group = "restore_what", value_parser = cli_parse_pathbuf_lenient, value_hint = ValueHint::AnyPath)]
+    pub origin: Option<PathBuf>,
+    /// Only restore backups from this backup run (see `--if-exists stash`). Without an origin,
+    /// every backup in the run is restored, undoing the run.
+    #[arg(short, long, group = "restore_what", value_name = "RUN")]
+    pub run: Option<String>,
+    /// Generation of the backup to restore (e.g. `0` for `.bak0`). [default: newest]
+    #[arg(short, long)]
+    pub generation: Option<usize>,
+    /// Restore even if the origin is a real file instead of a link. It is stashed first (see `--if-exists stash`).
+    #[arg(long)]
+    pub force: bool,
+
+    #[command(flatten)]
+    pub search: BackupSearchArgs,
+}
+
+/// Delete old backups.
+#[derive(Clone, Debug, Args)]
+#[command(group = clap::ArgGroup::new("prune_by").required(true).multiple(true))]
+pub struct BackupPruneCli {
+    /// Keep this many of the newest backups of each backed up file.
+    #[arg(short, long, group = "prune_by")]
+    pub keep: Option<usize>,
+    /// Delete backups older than this, e.g. `30d`. Units are s, m, h, d, and w.
+    #[arg(short, long, group = "prune_by", value_parser = cli_parse_duration, value_name = "AGE")]
+    pub older_than: Option<Duration>,
+    /// Dry run; show what would be deleted, but do not delete it.
+    #[arg(short = 'd', long)]
+    pub dry_run: bool,
+    /// Do not ask for confirmation before deleting backups.
+    #[arg(short, long)]
+    pub yes: bool,
+
+    #[command(flatten)]
+    pub search: BackupSearchArgs,
+}
+
+/// Subcommands for `unbox backups`.
+#[derive(Clone, Debug, Subcommand)]
+pub enum BackupCommand {
+    List(BackupListCli),
+    Restore(BackupRestoreCli),
+    Prune(BackupPruneCli),
+}
+
+/// Subcommands for `unbox`.
+#[derive(Clone, Debug, Subcommand)]
+pub enum UnboxCommand {
+    /// List packages with their resolved target, link type, and whether they are unboxed.
+    List(ListCli),
+    /// Manage backups of files that were moved out of the way by unboxing.
+    #[command(subcommand)]
+    Backups(BackupCommand),
+    /// Move existing files into a package and link them back.
+    Import(ImportCli),
+}
+
 /// boxunbox is a symlinker inspired by GNU stow.
 #[derive(Clone, Debug, Parser)]
 #[command(name = "boxup", about, long_about = None, styles=__cli_styles(), version)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct BoxUpCli {
     /// Package (directory) to box up. Specify multiple directories to box multiple.
     #[arg(required = true, value_parser = cli_parse_pathbuf, value_hint = ValueHint::DirPath)]
@@ -76,7 +288,7 @@ pub struct BoxUpCli {
     /// When to show color.
     #[arg(long = "color", default_value_t = ColorOverride::default(), value_name = "WHEN")]
     pub color_override: ColorOverride,
-    /// Dry run; show the unboxing plan, but do not execute it.
+    /// Dry run; show the boxing plan, but do not execute it.
     #[arg(short = 'd', long)]
     pub dry_run: bool,
     /// Exclude file names with a regex. May be specified multiple times, overrides --include.
@@ -91,6 +303,10 @@ pub struct BoxUpCli {
     /// When enabled, exits on the first error.
     #[arg(short, long)]
     pub fail_fast: bool,
+    /// Box up packages even if other unboxed packages require them, and remove recorded links even
+    /// if they no longer link into the package.
+    #[arg(long)]
+    pub force: bool,
     /// Include file names with a regex. May be specified multiple times.
     ///
     /// This is the opposite of --exclude. When an include pattern is specified, all files are
@@ -101,26 +317,54 @@ pub struct BoxUpCli {
     /// include AND exclude pattern, it will ultimately be excluded.
     #[arg(short, long = "include", value_name = "REGEX")]
     pub include_pats: Vec<Regex>,
-    /// Do not remove the `.bub.last` file after boxing up a package.
+    /// Do not remove the unbox manifest after boxing up a package.
     #[arg(short, long)]
     pub keep_last_file: bool,
+    /// Do not restore files that were moved to a backup when unboxing (i.e. with
+    /// `--if-exists move`). The backups are kept where they are, but are no longer recorded, so
+    /// `unbox backups` won't show or prune them.
+    #[arg(long)]
+    pub no_restore: bool,
+    /// Find the links to box up by re-planning the unboxing and checking which links point into
+    /// the package, instead of reading the unbox manifest. This is done automatically if the
+    /// package has no manifest. Found links are listed for review before they are removed.
+    #[arg(short, long)]
+    pub scan: bool,
+    /// Only box up the links unboxed to this target directory. By default, a package is boxed up
+    /// from every target it was unboxed to. When scanning, this target is scanned instead of the
+    /// configured one.
+    #[arg(short, long, value_parser = cli_parse_pathbuf, value_hint = ValueHint::DirPath)]
+    pub target: Option<PathBuf>,
+    /// Do not ask for confirmation before removing links found with `--scan`.
+    #[arg(short, long)]
+    pub yes: bool,
 }
 
 /// boxunbox is a symlinker inspired by GNU stow.
 #[derive(Clone, Debug, Parser)]
-#[command(name = "unbox", about, long_about = None, styles=__cli_styles(), version)]
+#[command(name = "unbox", about, long_about = None, styles=__cli_styles(), version, subcommand_negates_reqs = true)]
 #[allow(clippy::struct_excessive_bools)]
 pub struct UnboxCli {
     /// Package (directory) to unbox. Specify multiple directories to unbox multiple.
-    #[arg(required = true, value_parser = cli_parse_pathbuf, value_hint = ValueHint::DirPath)]
+    ///
+    /// A package named like a subcommand (`list`, `backups`, or `import`) is taken as that
+    /// subcommand, so give its path instead, e.g. `./list`.
+    #[arg(required_unless_present = "stow_dir", value_parser = cli_parse_pathbuf, value_hint = ValueHint::DirPath)]
     pub packages: Vec<PathBuf>,
 
+    #[command(subcommand)]
+    pub command: Option<UnboxCommand>,
+
     /// When to show color.
     #[arg(long = "color", default_value_t = ColorOverride::default(), value_name = "WHEN")]
     pub color_override: ColorOverride,
     /// Dry run; show the unboxing plan, but do not execute it.
     #[arg(short = 'd', long)]
     pub dry_run: bool,
+    /// Show a diff of each existing target file against the package file that would replace it.
+    /// Binary files are summarized by their sizes and hashes instead.
+    #[arg(long)]
+    pub diff: bool,
     /// Exclude file names with a regex. May be specified multiple times, overrides --include.
     ///
     /// When an exclude pattern is specified, all files are included by default unless they match
@@ -130,10 +374,18 @@ pub struct UnboxCli {
     /// an explanation of syntax and <https://regex101.com/> for testing regex patterns.
     #[arg(short = 'x', long = "exclude", value_name = "REGEX")]
     pub exclude_pats: Vec<Regex>,
+    #[command(flatten)]
+    pub discovery: DiscoveryArgs,
     /// What to do if a file already exists in the target. This has no effect on symlinks that are
     /// created successfully.
-    #[arg(short = 'e', long = "if-exists", default_value_t = ExistingFileStrategy::default(), value_name = "STRATEGY")]
-    pub existing_file_strategy: ExistingFileStrategy,
+    ///
+    /// This overrides the package config's `if_exists` and `if_exists_rules`. [default: error]
+    #[arg(short = 'e', long = "if-exists", value_name = "STRATEGY")]
+    pub existing_file_strategy: Option<ExistingFileStrategy>,
+    /// What to do if a file already exists in the target with `--if-exists ask`, but stdin is not
+    /// a terminal. This can't be `ask`.
+    #[arg(long, default_value_t = ExistingFileStrategy::default(), value_name = "STRATEGY", value_parser = cli_ask_fallback_parser())]
+    pub ask_fallback: ExistingFileStrategy,
     /// Include file names with a regex. May be specified multiple times.
     ///
     /// This is the opposite of --exclude. When an include pattern is specified, all files are
@@ -150,9 +402,13 @@ pub struct UnboxCli {
     /// the `/path/to/package` directory.
     #[arg(short = 'r', long)]
     pub link_root: bool,
-    /// Type of link to create.
+    /// Type of link to create. This overrides the package config's `link_type_rules`.
     #[arg(short, long, value_name = "TYPE")]
     pub link_type: Option<LinkType>,
+    /// What to do instead of hard linking a file if the package and target are on different
+    /// filesystems. This is checked for each link while planning. [default: error]
+    #[arg(long, value_name = "FALLBACK")]
+    pub hard_link_fallback: Option<HardLinkFallback>,
     /// Save the current CLI parameters to a config file. WARNING: overwrites any existing file!
     ///
     /// When specified in conjunction with `--save-os-config`, both options are respected and two
@@ -171,24 +427,21 @@ pub struct UnboxCli {
     #[arg(short, long, value_parser = cli_parse_pathbuf, value_hint = ValueHint::DirPath)]
     pub target: Option<PathBuf>,
 
+    /// With `--if-exists adopt`, adopt the files that existing target symlinks point to instead of
+    /// throwing an error. Symlinks pointing into the package are replaced.
+    #[arg(long)]
+    pub adopt_symlinks: bool,
+    /// Treat target files that are identical to the package file like any other existing file
+    /// instead of replacing them with links.
+    #[arg(long)]
+    pub no_replace_identical: bool,
+
     /// Do not create directories at target locations.
     #[cfg(debug_assertions)]
     #[arg(long)]
     pub no_create_dirs: bool,
 }
 
-impl Default for ColorOverride {
-    fn default() -> Self {
-        Self::Auto
-    }
-}
-
-impl Default for ExistingFileStrategy {
-    fn default() -> Self {
-        Self::ThrowError
-    }
-}
-
 impl Display for ColorOverride {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match self {
@@ -207,32 +460,95 @@ impl Display for ExistingFileStrategy {
             ExistingFileStrategy::ThrowError => "error",
             ExistingFileStrategy::Ignore => "ignore",
             ExistingFileStrategy::Move => "move",
+            ExistingFileStrategy::Stash => "stash",
             ExistingFileStrategy::Overwrite => "overwrite",
+            ExistingFileStrategy::Trash => "trash",
             ExistingFileStrategy::Adopt => "adopt",
+            ExistingFileStrategy::Ask => "ask",
         };
 
         write!(f, "{s}")
     }
 }
 
-#[cfg(test)]
 impl UnboxCli {
+    /// Create an [`UnboxCli`] for `package` with every flag set to its default. This is used to
+    /// plan unboxings outside of `unbox` (e.g. by `boxup`) and in tests.
+    ///
+    /// # Arguments
+    ///
+    /// - `package` - Package to unbox.
     pub(crate) fn new<P: Into<PathBuf>>(package: P) -> Self {
         Self {
             packages: vec![package.into()],
+            command: None,
             color_override: ColorOverride::default(),
             dry_run: false,
-            existing_file_strategy: ExistingFileStrategy::default(),
+            diff: false,
+            discovery: DiscoveryArgs::default(),
+            existing_file_strategy: None,
+            ask_fallback: ExistingFileStrategy::default(),
             exclude_pats: Vec::default(),
             include_pats: Vec::default(),
             link_root: false,
             link_type: None,
+            hard_link_fallback: None,
             save_config: false,
             save_os_config: false,
             target: None,
+            no_replace_identical: false,
+            adopt_symlinks: false,
 
             #[cfg(debug_assertions)]
             no_create_dirs: false,
         }
     }
+
+    /// Get a copy of this [`UnboxCli`] without the flags that override or save package configs
+    /// (e.g. `--target`). Packages that are only unboxed because another package requires them
+    /// are unboxed with this, so they use their own configs.
+    #[must_use]
+    pub fn without_config_overrides(&self) -> Self {
+        Self {
+            exclude_pats: Vec::default(),
+            include_pats: Vec::default(),
+            existing_file_strategy: None,
+            link_root: false,
+            link_type: None,
+            hard_link_fallback: None,
+            save_config: false,
+            save_os_config: false,
+            target: None,
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ask_fallback_rejects_ask() {
+        let parse = |fallback| UnboxCli::try_parse_from(["unbox", "--ask-fallback", fallback, "."]);
+        assert!(parse("ask").is_err(), "falling back to asking was accepted");
+        let cli = parse("move").expect("fallback should parse");
+        assert!(matches!(cli.ask_fallback, ExistingFileStrategy::Move));
+    }
+
+    #[test]
+    fn test_package_named_like_subcommand() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir().context("failed to create tempdir")?;
+        let package = temp_dir.path().join("list");
+        std::fs::create_dir(&package).context("failed to create test package")?;
+
+        let cli = UnboxCli::try_parse_from(["unbox", "list"])?;
+        assert!(cli.packages.is_empty());
+        assert!(matches!(cli.command, Some(UnboxCommand::List(_))));
+        let cli = UnboxCli::try_parse_from([std::ffi::OsStr::new("unbox"), package.as_os_str()])?;
+        assert_eq!(cli.packages, [dunce::canonicalize(&package)?]);
+        assert!(cli.command.is_none());
+
+        Ok(())
+    }
 }
@@ -3,3 +3,85 @@ use std::sync::LazyLock;
 /// Lazy wrapper around [`directories_next::BaseDirs::new`].
 pub static BASE_DIRS: LazyLock<directories_next::BaseDirs> =
     LazyLock::new(|| directories_next::BaseDirs::new().expect("user should have a home directory"));
+
+/// Name of the old, plain text file that lists the links created by unboxing a package. This is
+/// only read to migrate it to an [`crate::manifest::UnboxManifest`].
+pub const LAST_UNBOXING_FILE_NAME: &str = ".bub.last";
+
+/// Name of the file that stores the [`crate::manifest::UnboxManifest`] of a package.
+pub const MANIFEST_FILE_NAME: &str = ".bub.manifest.json";
+
+/// Directory boxunbox keeps its per-user state in, such as the
+/// [`crate::manifest::UnboxManifest`] registry. This follows the XDG base directory spec on Linux
+/// (`$XDG_STATE_HOME/boxunbox`, defaulting to `~/.local/state/boxunbox`) and uses the local data
+/// directory elsewhere.
+#[cfg(not(test))]
+pub static STATE_DIR: LazyLock<std::path::PathBuf> = LazyLock::new(|| {
+    if cfg!(target_os = "linux") {
+        std::env::var_os("XDG_STATE_HOME")
+            .map(std::path::PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .unwrap_or_else(|| BASE_DIRS.home_dir().join(".local/state"))
+            .join("boxunbox")
+    } else {
+        BASE_DIRS.data_local_dir().join("boxunbox")
+    }
+});
+
+/// Home trash directory from the
+/// [freedesktop trash specification](https://specifications.freedesktop.org/trash-spec/latest/),
+/// which is `$XDG_DATA_HOME/Trash` (defaulting to `~/.local/share/Trash`) on Linux.
+#[cfg(not(test))]
+pub static TRASH_DIR: LazyLock<std::path::PathBuf> =
+    LazyLock::new(|| BASE_DIRS.data_dir().join("Trash"));
+
+/// **_Test_** state directory, unique to each test so tests never touch the real state or each
+/// other's. See [`TestStateDir`].
+#[cfg(test)]
+pub static STATE_DIR: TestStateDir = TestStateDir::State;
+
+/// **_Test_** trash directory, inside the test state directory so tests never touch the real
+/// trash.
+#[cfg(test)]
+pub static TRASH_DIR: TestStateDir = TestStateDir::Trash;
+
+/// **_Test_** stand-in for the state and trash directories. Every test runs on its own thread, so
+/// each thread gets its own temporary state directory, which is removed when the test's thread
+/// exits.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug)]
+pub enum TestStateDir {
+    /// The state directory itself
+    State,
+    /// The trash directory in the state directory
+    Trash,
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestStateDir {
+    type Target = std::path::PathBuf;
+
+    fn deref(&self) -> &Self::Target {
+        thread_local! {
+            // the paths are leaked so they can be borrowed like a static, which is fine for tests
+            static STATE_ROOT: (
+                tempfile::TempDir,
+                &'static std::path::PathBuf,
+                &'static std::path::PathBuf,
+            ) = {
+                let dir = tempfile::Builder::new()
+                    .prefix("boxunbox-test-state-")
+                    .tempdir()
+                    .expect("failed to create test state dir");
+                let state_dir = Box::leak(Box::new(dir.path().to_path_buf()));
+                let trash_dir = Box::leak(Box::new(dir.path().join("Trash")));
+                (dir, state_dir, trash_dir)
+            };
+        }
+
+        STATE_ROOT.with(|(_, state_dir, trash_dir)| match self {
+            TestStateDir::State => *state_dir,
+            TestStateDir::Trash => *trash_dir,
+        })
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    discovery::discover_packages,
+    error::DependencyError,
+    manifest::UnboxManifest,
+    package::{PackageConfig, error::ConfigRead},
+};
+
+/// Read the canonical paths of the packages required by `package`. Packages without a config (or
+/// with an old RON config) have no requirements.
+///
+/// # Arguments
+///
+/// - `package` - Package to read requirements for.
+///
+/// # Errors
+///
+/// An error is returned if the package config cannot be parsed or a required package does not
+/// exist.
+pub fn read_requirements<P: AsRef<Path>>(package: P) -> Result<Vec<PathBuf>, DependencyError> {
+    let package = package.as_ref();
+    let requires = match PackageConfig::try_from_package(package) {
+        Ok(config) => config.requires,
+        Err(ConfigRead::FileNotFound(_)) => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    requires
+        .into_iter()
+        .map(|requirement| {
+            // `join` replaces the path entirely if `requirement` is absolute
+            let joined = package.join(&requirement);
+            dunce::canonicalize(&joined).map_err(|err| DependencyError::MissingRequirement {
+                package: package.to_path_buf(),
+                requirement,
+                source: err,
+            })
+        })
+        .collect()
+}
+
+/// Resolve the order to unbox `packages` in with a topological sort. Every required package is
+/// included in the result, even if it is not in `packages`, and is ordered before the packages
+/// that require it. Otherwise, the order of `packages` is preserved.
+///
+/// # Arguments
+///
+/// - `packages` - Canonical package paths to resolve.
+///
+/// # Errors
+///
+/// An error is returned if [`read_requirements`] fails or there is a dependency cycle.
+pub fn resolve_unbox_order(packages: &[PathBuf]) -> Result<Vec<PathBuf>, DependencyError> {
+    /// Depth-first visit of `package`, pushing it to `order` after all of its requirements.
+    /// `stack` holds the packages currently being visited and is used to detect cycles.
+    fn visit(
+        package: &Path,
+        stack: &mut Vec<PathBuf>,
+        order: &mut Vec<PathBuf>,
+    ) -> Result<(), DependencyError> {
+        if order.iter().any(|p| p == package) {
+            return Ok(());
+        }
+
+        if let Some(pos) = stack.iter().position(|p| p == package) {
+            let mut cycle = stack[pos..].to_vec();
+            cycle.push(package.to_path_buf());
+            return Err(DependencyError::Cycle(cycle));
+        }
+
+        stack.push(package.to_path_buf());
+        for requirement in read_requirements(package)? {
+            visit(&requirement, stack, order)?;
+        }
+        stack.pop();
+
+        order.push(package.to_path_buf());
+        Ok(())
+    }
+
+    let mut order = Vec::with_capacity(packages.len());
+    let mut stack = Vec::new();
+    for package in packages {
+        visit(package, &mut stack, &mut order)?;
+    }
+
+    Ok(order)
+}
+
+/// Find the currently unboxed packages that require `package`. The siblings of `package` (i.e. the
+/// other packages in its parent directory) and every package in the state registry are searched.
+///
+/// # Arguments
+///
+/// - `package` - Canonical package path to find dependents of.
+///
+/// # Errors
+///
+/// An error is returned if the parent directory or state registry cannot be read or
+/// [`read_requirements`] fails.
+pub fn find_unboxed_dependents<P: AsRef<Path>>(
+    package: P,
+) -> Result<Vec<PathBuf>, DependencyError> {
+    let package = package.as_ref();
+    let Some(parent) = package.parent() else {
+        return Ok(Vec::new());
+    };
+
+    let mut candidates =
+        discover_packages(parent, false, &[]).map_err(|err| DependencyError::Io {
+            path: parent.to_path_buf(),
+            source: err,
+        })?;
+    // registered packages are unboxed by definition, but may live anywhere
+    let registered_packages = UnboxManifest::all_registered()?
+        .into_iter()
+        .map(|m| m.package)
+        .collect::<Vec<_>>();
+    for registered_package in &registered_packages {
+        if !candidates.contains(registered_package) && registered_package.exists() {
+            candidates.push(registered_package.clone());
+        }
+    }
+    let registered_packages = registered_packages.into_iter().collect::<HashSet<_>>();
+
+    let mut dependents = Vec::new();
+    for candidate in candidates {
+        if candidate == package || !UnboxManifest::is_unboxed_in(&candidate, &registered_packages) {
+            continue;
+        }
+
+        if read_requirements(&candidate)?
+            .iter()
+            .any(|req| req == package)
+        {
+            dependents.push(candidate);
+        }
+    }
+
+    Ok(dependents)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use anyhow::Context;
+
+    use crate::{
+        constants::MANIFEST_FILE_NAME,
+        manifest::{LinkOutcome, StateLocation},
+        package::LinkType,
+        plan::PlannedLink,
+    };
+
+    use super::*;
+
+    /// Create a package named `name` in `stow_dir` that requires `requires`.
+    fn make_package(stow_dir: &Path, name: &str, requires: &[&str]) -> anyhow::Result<PathBuf> {
+        let package = stow_dir.join(name);
+        fs::create_dir(&package).with_context(|| format!("failed to create package {name}"))?;
+        let mut conf = PackageConfig::new(&package);
+        conf.requires = requires.iter().map(PathBuf::from).collect();
+        conf.save_to_package()
+            .with_context(|| format!("failed to save config for {name}"))?;
+
+        Ok(package)
+    }
+
+    #[test]
+    fn test_resolve_unbox_order() -> anyhow::Result<()> {
+        let stow_dir = tempfile::tempdir().context("failed to create temp stow dir")?;
+        let stow_path = dunce::canonicalize(stow_dir.path())?;
+        let zsh = make_package(&stow_path, "zsh", &[])?;
+        let plugins = make_package(&stow_path, "zsh-plugins", &["../zsh"])?;
+        let theme = make_package(&stow_path, "zsh-theme", &["../zsh-plugins", "../zsh"])?;
+
+        let order = resolve_unbox_order(&[theme.clone(), plugins.clone()])
+            .context("failed to resolve unbox order")?;
+        assert_eq!(order, vec![zsh, plugins, theme]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_unbox_order_cycle() -> anyhow::Result<()> {
+        let stow_dir = tempfile::tempdir().context("failed to create temp stow dir")?;
+        let stow_path = dunce::canonicalize(stow_dir.path())?;
+        let a = make_package(&stow_path, "a", &["../b"])?;
+        let b = make_package(&stow_path, "b", &["../a"])?;
+
+        match resolve_unbox_order(std::slice::from_ref(&a)) {
+            Err(DependencyError::Cycle(cycle)) => assert_eq!(cycle, vec![a.clone(), b, a]),
+            res => panic!("expected dependency cycle error, got {res:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_unbox_order_missing() -> anyhow::Result<()> {
+        let stow_dir = tempfile::tempdir().context("failed to create temp stow dir")?;
+        let stow_path = dunce::canonicalize(stow_dir.path())?;
+        let a = make_package(&stow_path, "a", &["../does_not_exist"])?;
+
+        let res = resolve_unbox_order(&[a]);
+        assert!(
+            matches!(res, Err(DependencyError::MissingRequirement { .. })),
+            "expected missing requirement error, got {res:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_unboxed_dependents() -> anyhow::Result<()> {
+        let stow_dir = tempfile::tempdir().context("failed to create temp stow dir")?;
+        let stow_path = dunce::canonicalize(stow_dir.path())?;
+        let zsh = make_package(&stow_path, "zsh", &[])?;
+        let plugins = make_package(&stow_path, "zsh-plugins", &["../zsh"])?;
+        let _theme = make_package(&stow_path, "zsh-theme", &["../zsh"])?;
+
+        // only zsh-plugins is unboxed, and it keeps its manifest in the package
+        let mut config = PackageConfig::new(&plugins);
+        config.state_location = StateLocation::Package;
+        let mut manifest = UnboxManifest::load(&config).context("failed to load test manifest")?;
+        let pl = PlannedLink::new(
+            plugins.join("plugins.zsh"),
+            config.target.join("plugins.zsh"),
+            LinkType::SymlinkAbsolute,
+        );
+        manifest.record(&pl, LinkOutcome::Created, None);
+        manifest.save().context("failed to save test manifest")?;
+        assert!(plugins.join(MANIFEST_FILE_NAME).exists());
+
+        let dependents = find_unboxed_dependents(&zsh).context("failed to find dependents")?;
+        assert_eq!(dependents, vec![plugins]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_unboxed_dependents_registered() -> anyhow::Result<()> {
+        let stow_dir = tempfile::tempdir().context("failed to create temp stow dir")?;
+        let stow_path = dunce::canonicalize(stow_dir.path())?;
+        let other_stow_dir = tempfile::tempdir().context("failed to create other stow dir")?;
+        let other_stow_path = dunce::canonicalize(other_stow_dir.path())?;
+        let zsh = make_package(&stow_path, "zsh", &[])?;
+        let requirement = zsh.to_string_lossy().to_string();
+        let plugins = make_package(&other_stow_path, "zsh-plugins", &[&requirement])?;
+
+        // zsh-plugins is only unboxed according to the registry
+        UnboxManifest::new(&PackageConfig::new(&plugins))
+            .save()
+            .context("failed to save test manifest")?;
+
+        let dependents = find_unboxed_dependents(&zsh).context("failed to find dependents")?;
+        assert_eq!(dependents, vec![plugins]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+use std::{fmt::Write as _, fs, io, path::Path};
+
+use colored::Colorize;
+
+use crate::utils::{fnv1a_64, replace_home_with_tilde};
+
+/// Lines of context shown around each change in a unified diff.
+const CONTEXT_LINES: usize = 3;
+
+/// Largest number of old lines times new lines that is diffed. Diffing takes memory proportional
+/// to this, so bigger files are only summarized (see [`__summarize`]).
+const MAX_DIFF_CELLS: usize = 16_000_000;
+
+/// Create a unified diff from the file at `old_path` to the file at `new_path`. Files that aren't
+/// text or are too large to diff are only summarized by their sizes and hashes.
+///
+/// # Arguments
+///
+/// - `old_path` - File to diff from, usually the existing target file.
+/// - `new_path` - File to diff to, usually the package file.
+///
+/// # Errors
+///
+/// An error is returned if either file cannot be read.
+pub fn unified_diff<P: AsRef<Path>, Q: AsRef<Path>>(
+    old_path: P,
+    new_path: Q,
+) -> io::Result<String> {
+    let old_path = old_path.as_ref();
+    let new_path = new_path.as_ref();
+    let old_name = replace_home_with_tilde(old_path);
+    let new_name = replace_home_with_tilde(new_path);
+    let old_bytes = fs::read(old_path)?;
+    let new_bytes = fs::read(new_path)?;
+
+    if old_bytes == new_bytes {
+        return Ok(format!("Files {old_name} and {new_name} are identical\n"));
+    }
+    let (Some(old_text), Some(new_text)) = (__as_text(&old_bytes), __as_text(&new_bytes)) else {
+        return Ok(__summarize(
+            "Binary files differ",
+            [(&old_name, &old_bytes), (&new_name, &new_bytes)],
+        ));
+    };
+    let old_lines = old_text.lines().collect::<Vec<_>>();
+    let new_lines = new_text.lines().collect::<Vec<_>>();
+    if old_lines.len().saturating_mul(new_lines.len()) > MAX_DIFF_CELLS {
+        return Ok(__summarize(
+            "Files differ, but are too large to diff",
+            [(&old_name, &old_bytes), (&new_name, &new_bytes)],
+        ));
+    }
+
+    let mut diff = format!("--- {old_name}\n+++ {new_name}\n");
+    let ops = __diff_lines(&old_lines, &new_lines);
+    for (start, end) in __group_hunks(&ops) {
+        let count = |range: &[(char, &str)], side: char| {
+            range
+                .iter()
+                .filter(|(op, _)| *op == ' ' || *op == side)
+                .count()
+        };
+        let (old_before, new_before) = (count(&ops[..start], '-'), count(&ops[..start], '+'));
+        let (old_len, new_len) = (count(&ops[start..end], '-'), count(&ops[start..end], '+'));
+        // empty ranges start at the line before them
+        let old_start = old_before + usize::from(old_len > 0);
+        let new_start = new_before + usize::from(new_len > 0);
+
+        let _ = writeln!(diff, "@@ -{old_start},{old_len} +{new_start},{new_len} @@");
+        for (op, line) in &ops[start..end] {
+            let _ = writeln!(diff, "{op}{line}");
+        }
+    }
+
+    Ok(diff)
+}
+
+/// Describe how an existing target file differs from the package file that would replace it (see
+/// [`unified_diff`]). Directories and files that cannot be read are described instead of diffed.
+///
+/// # Arguments
+///
+/// - `dest` - Existing target file.
+/// - `src` - Package file.
+#[must_use]
+pub fn conflict_diff(dest: &Path, src: &Path) -> String {
+    if let Some(dir) = [dest, src].into_iter().find(|p| p.is_dir()) {
+        return format!(
+            "{} is a directory, not diffing it with {}\n",
+            replace_home_with_tilde(dir),
+            replace_home_with_tilde(if dir == dest { src } else { dest })
+        );
+    }
+
+    unified_diff(dest, src)
+        .unwrap_or_else(|err| format!("cannot diff {}: {err}\n", replace_home_with_tilde(dest)))
+}
+
+/// Color a diff from [`unified_diff`] for the terminal: removed lines are red, added lines are
+/// green, and hunk headers are cyan.
+///
+/// # Arguments
+///
+/// - `diff` - Diff to color.
+#[must_use]
+pub fn colorize_diff(diff: &str) -> String {
+    diff.lines().fold(String::new(), |mut colored_diff, line| {
+        let colored_line = if line.starts_with("---") || line.starts_with("+++") {
+            line.bold()
+        } else if line.starts_with("@@") {
+            line.cyan()
+        } else if line.starts_with('-') {
+            line.red()
+        } else if line.starts_with('+') {
+            line.green()
+        } else {
+            line.normal()
+        };
+        let _ = writeln!(colored_diff, "{colored_line}");
+        colored_diff
+    })
+}
+
+/// Summarize files that can't be diffed line by line with their sizes and hashes.
+///
+/// # Arguments
+///
+/// - `header` - First line of the summary.
+/// - `files` - Name and contents of each file.
+fn __summarize(header: &str, files: [(&str, &[u8]); 2]) -> String {
+    files
+        .iter()
+        .fold(format!("{header}:\n"), |mut summary, (name, bytes)| {
+            let _ = writeln!(
+                summary,
+                "  {name}: {} bytes, fnv1a {:016x}",
+                bytes.len(),
+                fnv1a_64(bytes)
+            );
+            summary
+        })
+}
+
+/// Returns `bytes` as text, or [`None`] if they look binary (i.e. they contain a NUL byte or
+/// aren't UTF-8).
+///
+/// # Arguments
+///
+/// - `bytes` - Contents of a file.
+fn __as_text(bytes: &[u8]) -> Option<&str> {
+    if bytes.contains(&0) {
+        None
+    } else {
+        std::str::from_utf8(bytes).ok()
+    }
+}
+
+/// Diff two lists of lines using their longest common subsequence. Returns each line with `' '`
+/// if it is in both, `'-'` if it is only in `old`, or `'+'` if it is only in `new`.
+///
+/// # Arguments
+///
+/// - `old` - Lines to diff from.
+/// - `new` - Lines to diff to.
+fn __diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    let width = new.len() + 1;
+    // lcs[i * width + j] is the length of the LCS of old[i..] and new[j..]
+    let mut lcs = vec![0_u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(old.len() + new.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len()
+            || (i < old.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+        {
+            ops.push(('-', old[i]));
+            i += 1;
+        } else {
+            ops.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    ops
+}
+
+/// Group the changes in a line diff into hunks with [`CONTEXT_LINES`] of context. Hunks whose
+/// context would overlap are merged. Returns the range of each hunk in `ops`.
+///
+/// # Arguments
+///
+/// - `ops` - Line diff from [`__diff_lines`].
+fn __group_hunks(ops: &[(char, &str)]) -> Vec<(usize, usize)> {
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in ops.iter().enumerate().filter(|(_, (op, _))| *op != ' ') {
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + CONTEXT_LINES + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir().context("failed to create temp dir")?;
+        let old_path = dir.path().join("old.txt");
+        let new_path = dir.path().join("new.txt");
+        let old_lines = (1..=10)
+            .map(|i| format!("line {i}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+            + "\n";
+        let new_lines = old_lines
+            .replace("line 2\n", "line two\n")
+            .replace("line 10\n", "");
+        fs::write(&old_path, &old_lines).context("failed to write old file")?;
+        fs::write(&new_path, &new_lines).context("failed to write new file")?;
+
+        let diff = unified_diff(&old_path, &new_path).context("failed to diff files")?;
+        let expected_hunks = "\
+@@ -1,5 +1,5 @@
+ line 1
+-line 2
++line two
+ line 3
+ line 4
+ line 5
+@@ -7,4 +7,3 @@
+ line 7
+ line 8
+ line 9
+-line 10
+";
+        assert!(diff.ends_with(expected_hunks), "unexpected diff:\n{diff}");
+
+        fs::write(&new_path, b"\0binary").context("failed to write binary file")?;
+        let diff = unified_diff(&old_path, &new_path).context("failed to diff files")?;
+        assert!(
+            diff.starts_with("Binary files differ:\n") && diff.contains(": 7 bytes, fnv1a "),
+            "unexpected diff:\n{diff}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflict_diff() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir().context("failed to create temp dir")?;
+        let file_path = dir.path().join("file.txt");
+        let dir_path = dir.path().join("dir");
+        fs::write(&file_path, "file").context("failed to write test file")?;
+        fs::create_dir(&dir_path).context("failed to create test dir")?;
+
+        let diff = conflict_diff(&dir_path, &file_path);
+        assert!(diff.contains("is a directory"), "unexpected diff:\n{diff}");
+        let diff = conflict_diff(&dir.path().join("missing.txt"), &file_path);
+        assert!(diff.starts_with("cannot diff"), "unexpected diff:\n{diff}");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use regex::Regex;
+
+use crate::cli::DiscoveryArgs;
+
+/// Matches package config file names, both new (`.bub.toml`, `.bub.<os>.toml`) and old
+/// (`.unboxrc.ron`, `.unboxrc.<os>.ron`).
+static CONFIG_FILE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(\.bub(\.\w+?)?\.toml|\.unboxrc(\.\w+?)?\.ron)$")
+        .expect("config file regex should compile")
+});
+
+/// Returns `true` if the directory `dir` contains a package config file of any kind.
+///
+/// # Arguments
+///
+/// - `dir` - Directory to check.
+///
+/// # Errors
+///
+/// An error is returned if `dir` cannot be read.
+pub fn has_package_config<P: AsRef<Path>>(dir: P) -> io::Result<bool> {
+    for res in fs::read_dir(dir)? {
+        let entry = res?;
+        if entry.file_type()?.is_file()
+            && CONFIG_FILE_REGEX.is_match(&entry.file_name().to_string_lossy())
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Discover every package in the directory `stow_dir`. A package is any child directory that
+/// contains a package config file. In `stow_mode`, every child directory is considered a package,
+/// like GNU `stow`.
+///
+/// Packages are returned sorted by name and filtered by `select_pats`. If no select patterns are
+/// given, every discovered package is returned. Hidden directories (e.g. `.git`) are always
+/// skipped.
+///
+/// # Arguments
+///
+/// - `stow_dir` - Directory to search for packages in.
+/// - `stow_mode` - Whether every child directory is a package or not.
+/// - `select_pats` - [`Regex`]'s matched against the package directory names.
+///
+/// # Errors
+///
+/// An error is returned if `stow_dir` or any of its children cannot be read.
+pub fn discover_packages<P: AsRef<Path>>(
+    stow_dir: P,
+    stow_mode: bool,
+    select_pats: &[Regex],
+) -> io::Result<Vec<PathBuf>> {
+    let mut packages = Vec::new();
+    for res in fs::read_dir(stow_dir)? {
+        let entry = res?;
+        // `DirEntry::file_type` doesn't follow symlinks, but packages can be symlinks
+        if !entry.path().is_dir() {
+            continue;
+        }
+
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.starts_with('.') {
+            continue;
+        }
+
+        let is_selected =
+            select_pats.is_empty() || select_pats.iter().any(|re| re.is_match(&file_name));
+
+        if is_selected && (stow_mode || has_package_config(entry.path())?) {
+            packages.push(entry.path());
+        }
+    }
+
+    packages.sort();
+    Ok(packages)
+}
+
+/// Collect the list of packages to operate on from explicitly passed `packages` and any packages
+/// discovered with [`DiscoveryArgs`]. Discovered packages are appended after the explicit ones.
+/// Duplicates are skipped, comparing canonical paths so the same package reached through a
+/// symlink or `..` is only collected once.
+///
+/// # Arguments
+///
+/// - `packages` - Explicitly passed packages.
+/// - `args` - Discovery options.
+///
+/// # Errors
+///
+/// An error is returned if [`discover_packages`] fails.
+pub fn collect_packages(packages: &[PathBuf], args: &DiscoveryArgs) -> io::Result<Vec<PathBuf>> {
+    let discovered = match args.stow_dir.as_ref() {
+        Some(stow_dir) => discover_packages(stow_dir, args.stow_mode, &args.select_pats)?,
+        None => Vec::new(),
+    };
+
+    let mut collected = Vec::with_capacity(packages.len() + discovered.len());
+    let mut seen = Vec::with_capacity(collected.capacity());
+    for package in packages.iter().cloned().chain(discovered) {
+        // packages that don't exist are kept as-is so they fail later with a useful error
+        let canon_package = dunce::canonicalize(&package).unwrap_or_else(|_| package.clone());
+        if !seen.contains(&canon_package) {
+            seen.push(canon_package);
+            collected.push(package);
+        }
+    }
+
+    Ok(collected)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use crate::{test_utils::make_tmp_tree, utils::os_symlink};
+
+    use super::*;
+
+    /// Create a stow dir with three packages: `configured` (has a `.bub.toml`), `legacy` (has a
+    /// `.unboxrc.ron`), and `plain` (no config).
+    fn make_stow_dir() -> anyhow::Result<tempfile::TempDir> {
+        let stow_dir = tempfile::tempdir().context("failed to create temp stow dir")?;
+        let stow_path = stow_dir.path();
+        for name in ["configured", "legacy", "plain", ".git"] {
+            fs::create_dir(stow_path.join(name))
+                .with_context(|| format!("failed to create test package {name}"))?;
+        }
+        fs::write(stow_path.join("configured/.bub.toml"), "")
+            .context("failed to write test config")?;
+        fs::write(stow_path.join("legacy/.unboxrc.ron"), "PackageConfig()")
+            .context("failed to write test old config")?;
+        fs::write(stow_path.join("not_a_package.txt"), "").context("failed to write test file")?;
+
+        Ok(stow_dir)
+    }
+
+    #[test]
+    fn test_has_package_config() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let empty = tempfile::tempdir().context("failed to create empty dir")?;
+
+        assert!(has_package_config(package.path())?);
+        assert!(!has_package_config(empty.path())?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover_packages() -> anyhow::Result<()> {
+        let stow_dir = make_stow_dir()?;
+        let stow_path = stow_dir.path();
+
+        let discovered =
+            discover_packages(stow_path, false, &[]).context("failed to discover packages")?;
+        assert_eq!(
+            discovered,
+            vec![stow_path.join("configured"), stow_path.join("legacy")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover_packages_stow_mode() -> anyhow::Result<()> {
+        let stow_dir = make_stow_dir()?;
+        let stow_path = stow_dir.path();
+
+        let discovered =
+            discover_packages(stow_path, true, &[]).context("failed to discover packages")?;
+        assert_eq!(
+            discovered,
+            vec![
+                stow_path.join("configured"),
+                stow_path.join("legacy"),
+                stow_path.join("plain")
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_packages_dedupes_canonical_paths() -> anyhow::Result<()> {
+        let stow_dir = make_stow_dir()?;
+        let stow_path = stow_dir.path();
+        let args = DiscoveryArgs {
+            stow_dir: Some(stow_path.to_path_buf()),
+            ..DiscoveryArgs::default()
+        };
+        let dotted = stow_path.join("legacy").join("..").join("configured");
+        let linked = stow_path.join("linked");
+        os_symlink(stow_path.join("legacy"), &linked).context("failed to create test symlink")?;
+
+        let collected = collect_packages(&[dotted.clone(), linked.clone()], &args)
+            .context("failed to collect packages")?;
+        assert_eq!(collected, vec![dotted, linked]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover_packages_select() -> anyhow::Result<()> {
+        let stow_dir = make_stow_dir()?;
+        let stow_path = stow_dir.path();
+        let select_pats = vec![Regex::new("^(legacy|plain)$").context("failed to compile regex")?];
+
+        let discovered = discover_packages(stow_path, true, &select_pats)
+            .context("failed to discover packages")?;
+        assert_eq!(
+            discovered,
+            vec![stow_path.join("legacy"), stow_path.join("plain")]
+        );
+
+        Ok(())
+    }
+}
@@ -3,10 +3,82 @@ use std::path::PathBuf;
 use thiserror::Error as ThisError;
 
 use crate::{
+    hooks::HookStage,
     package::error::{ConfigRead, ConfigWrite},
     plan::PlannedLink,
 };
 
+#[derive(Debug, ThisError)]
+pub enum DependencyError {
+    #[error("failed to parse package config")]
+    ConfigParse(#[from] ConfigRead),
+    #[error("dependency cycle detected: {0:?}")]
+    Cycle(Vec<PathBuf>),
+    #[error("IO error")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to read unbox manifest")]
+    Manifest(#[from] ManifestError),
+    #[error("{package:?} requires {requirement:?}, which could not be found")]
+    MissingRequirement {
+        package: PathBuf,
+        requirement: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+#[derive(Debug, ThisError)]
+pub enum HookError {
+    #[error("command exited with {0}")]
+    ExitStatus(std::process::ExitStatus),
+    #[error("failed to run command")]
+    Spawn(#[from] std::io::Error),
+}
+
+#[derive(Debug, ThisError)]
+pub enum ImportError {
+    #[error("{0:?} already exists in the package")]
+    AlreadyInPackage(PathBuf),
+    #[error("failed to save TOML config")]
+    ConfigWrite(#[from] ConfigWrite),
+    #[error("IO error")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("cannot import into a package that links its root")]
+    LinkRoot,
+    #[error("failed to read or write unbox manifest")]
+    Manifest(#[from] ManifestError),
+    #[error("the package would not link {0:?} back, check its include and exclude patterns")]
+    NotLinked(Vec<PathBuf>),
+    #[error("{path:?} is not in the target {target:?}")]
+    NotInTarget { path: PathBuf, target: PathBuf },
+    #[error("{0:?} overlaps {1:?}")]
+    Overlapping(PathBuf, PathBuf),
+    #[error("failed to plan unboxing")]
+    Planning(#[from] PlanningError),
+    #[error("{0:?} is a symlink, only real files and directories can be imported")]
+    Symlink(PathBuf),
+    #[error("failed to link imported files")]
+    Unbox(#[from] UnboxError),
+}
+
+#[derive(Debug, ThisError)]
+pub enum ManifestError {
+    #[error("IO error")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to (de)serialize unbox manifest")]
+    Json(#[from] serde_json::Error),
+    #[error("unsupported unbox manifest version {0}")]
+    UnsupportedVersion(u32),
+}
+
 #[derive(Debug, ThisError)]
 pub enum PlanningError {
     #[error("failed to parse package config")]
@@ -16,14 +88,25 @@ pub enum PlanningError {
     ConfigWrite(#[from] ConfigWrite),
     #[error("nothing to unbox")]
     EmptyPlan,
+    #[error(
+        "cannot hard link {dest:?} to {src:?} across filesystems ({src_fs:?} and {dest_fs:?}), set a hard link fallback to symlink or copy it instead"
+    )]
+    HardLinkAcrossFilesystems {
+        src: PathBuf,
+        dest: PathBuf,
+        src_fs: PathBuf,
+        dest_fs: PathBuf,
+    },
     #[error("failed to walk package tree")]
     Walkdir(#[from] walkdir::Error),
 }
 
 #[derive(Debug, ThisError)]
 pub enum UnboxError {
-    #[error("cannot adopt symlink {0:?}")]
+    #[error("cannot adopt symlink {0:?} without --adopt-symlinks")]
     AdoptSymlink(PlannedLink),
+    #[error("refusing to adopt {pl:?}: package file {path:?} has uncommitted changes")]
+    AdoptUncommitted { pl: PlannedLink, path: PathBuf },
     #[error(
         "circular reference detected! {problem_link:?} is a symlink pointing to the src parent of {pl:?}"
     )]
@@ -36,6 +119,18 @@ pub enum UnboxError {
     #[warn(deprecated_in_future)]
     #[error("failed to save TOML config")]
     ConfigWrite(#[from] ConfigWrite),
+    #[error("{stage} hook failed: {command}")]
+    Hook {
+        stage: HookStage,
+        command: String,
+        source: HookError,
+    },
+    #[error("trigger for '{pattern}' failed: {command}")]
+    Trigger {
+        pattern: String,
+        command: String,
+        source: HookError,
+    },
     #[error("failed to unbox {pl:?}")]
     Unboxing {
         pl: PlannedLink,
@@ -46,6 +141,8 @@ pub enum UnboxError {
         path: std::path::PathBuf,
         source: std::io::Error,
     },
+    #[error("failed to read or write unbox manifest")]
+    Manifest(#[from] ManifestError),
     #[error("failed to plan unboxing")]
     Planning(#[from] PlanningError),
     #[error("target already exists for {0:?}")]
@@ -0,0 +1,420 @@
+use std::{
+    fmt::{self, Display},
+    path::Path,
+    process,
+};
+
+use colored::Colorize;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{HookError, UnboxError},
+    package::PackageConfig,
+    plan::PlannedLink,
+    utils::replace_home_with_tilde,
+};
+
+/// Describes when a hook is run.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HookStage {
+    /// Before planning an unboxing.
+    PreUnbox,
+    /// After a successful unboxing.
+    PostUnbox,
+    /// Before boxing up a package.
+    PreBoxup,
+    /// After boxing up a package.
+    PostBoxup,
+}
+
+/// Commands to run at each [`HookStage`]. Commands are run by the system shell (`sh -c` or
+/// `cmd /C`) from the package directory, so package-relative scripts can be run with something
+/// like `./hooks/post.sh`.
+///
+/// Hooks receive the following environment variables:
+///
+/// - `BUB_HOOK` - The [`HookStage`] being run, e.g. `post_unbox`.
+/// - `BUB_PACKAGE` - The package directory.
+/// - `BUB_TARGET` - The target directory.
+/// - `BUB_LINKS` - Newline-separated list of link paths in the target. This is empty for
+///   [`HookStage::PreUnbox`] hooks since nothing has been planned yet.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Hooks {
+    /// Commands to run before planning an unboxing.
+    #[serde(default = "Vec::default")]
+    pub pre_unbox: Vec<String>,
+    /// Commands to run after a successful unboxing.
+    #[serde(default = "Vec::default")]
+    pub post_unbox: Vec<String>,
+    /// Commands to run before boxing up.
+    #[serde(default = "Vec::default")]
+    pub pre_boxup: Vec<String>,
+    /// Commands to run after boxing up.
+    #[serde(default = "Vec::default")]
+    pub post_boxup: Vec<String>,
+}
+
+/// A command that only runs if a link matching [`Trigger::pattern`] was created, replaced, or
+/// adopted during an unboxing. Triggers are run like [`Hooks`] with `BUB_HOOK` set to `trigger`
+/// and `BUB_LINKS` set to only the matching links.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Trigger {
+    /// [`Regex`] matched against the path of each link's source, relative to the package.
+    #[serde(with = "serde_regex")]
+    pub pattern: Regex,
+    /// Command to run.
+    pub run: String,
+}
+
+impl Display for HookStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            HookStage::PreUnbox => "pre_unbox",
+            HookStage::PostUnbox => "post_unbox",
+            HookStage::PreBoxup => "pre_boxup",
+            HookStage::PostBoxup => "post_boxup",
+        };
+
+        write!(f, "{s}")
+    }
+}
+
+impl Hooks {
+    /// Get the commands for a given [`HookStage`].
+    #[must_use]
+    pub fn for_stage(&self, stage: HookStage) -> &[String] {
+        match stage {
+            HookStage::PreUnbox => &self.pre_unbox,
+            HookStage::PostUnbox => &self.post_unbox,
+            HookStage::PreBoxup => &self.pre_boxup,
+            HookStage::PostBoxup => &self.post_boxup,
+        }
+    }
+}
+
+/// Build a [`process::Command`] that runs `command` with the system shell.
+///
+/// # Arguments
+///
+/// - `command` - Shell command to run.
+pub(crate) fn shell_command(command: &str) -> process::Command {
+    #[cfg(windows)]
+    {
+        let mut cmd = process::Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    }
+
+    #[cfg(not(windows))]
+    {
+        let mut cmd = process::Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    }
+}
+
+/// Run `command` with the system shell from the package directory, passing the standard hook
+/// environment variables.
+///
+/// # Arguments
+///
+/// - `stage` - Name of the hook stage, passed as `BUB_HOOK`.
+/// - `command` - Shell command to run.
+/// - `config` - Config to read package and target from.
+/// - `links_str` - Newline-separated links, passed as `BUB_LINKS`.
+fn run_hook_command(
+    stage: &str,
+    command: &str,
+    config: &PackageConfig,
+    links_str: &str,
+) -> Result<(), HookError> {
+    let PackageConfig {
+        package, target, ..
+    } = config;
+
+    println!(
+        "running {stage} hook for {}: {}",
+        replace_home_with_tilde(package).bright_green(),
+        command.yellow()
+    );
+
+    let status = shell_command(command)
+        .current_dir(package)
+        .env("BUB_HOOK", stage)
+        .env("BUB_PACKAGE", package)
+        .env("BUB_TARGET", target)
+        .env("BUB_LINKS", links_str)
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(HookError::ExitStatus(status))
+    }
+}
+
+/// Join link paths into a newline-separated string for `BUB_LINKS`.
+fn join_links<P: AsRef<Path>>(links: &[P]) -> String {
+    links
+        .iter()
+        .map(|p| p.as_ref().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Run all hooks for the given `stage` from a [`PackageConfig`], stopping at the first failure.
+/// If `dry_run` is `true`, the hooks are printed but not run.
+///
+/// # Arguments
+///
+/// - `stage` - Which hooks to run.
+/// - `config` - Config to read hooks, package, and target from.
+/// - `links` - Link paths to pass to the hooks in `BUB_LINKS`.
+/// - `dry_run` - Print the hooks instead of running them.
+///
+/// # Errors
+///
+/// An error is returned if a hook cannot be run or exits with a non-zero status.
+pub fn run_hooks<P: AsRef<Path>>(
+    stage: HookStage,
+    config: &PackageConfig,
+    links: &[P],
+    dry_run: bool,
+) -> Result<(), UnboxError> {
+    let links_str = join_links(links);
+
+    for command in config.hooks.for_stage(stage) {
+        if dry_run {
+            println!("would run {stage} hook: {}", command.yellow());
+            continue;
+        }
+
+        run_hook_command(&stage.to_string(), command, config, &links_str).map_err(|err| {
+            UnboxError::Hook {
+                stage,
+                command: command.clone(),
+                source: err,
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Run every [`Trigger`] from a [`PackageConfig`] whose pattern matches at least one of the
+/// `changed_links`. Each trigger runs at most once. If `dry_run` is `true`, matching triggers are
+/// printed but not run; in that case, `changed_links` should be the planned links.
+///
+/// # Arguments
+///
+/// - `config` - Config to read triggers, package, and target from.
+/// - `changed_links` - Links that were created, replaced, or adopted.
+/// - `dry_run` - Print the triggers instead of running them.
+///
+/// # Errors
+///
+/// An error is returned if a trigger cannot be run or exits with a non-zero status.
+pub fn run_triggers(
+    config: &PackageConfig,
+    changed_links: &[PlannedLink],
+    dry_run: bool,
+) -> Result<(), UnboxError> {
+    for Trigger { pattern, run } in &config.triggers {
+        let matching_dests = changed_links
+            .iter()
+            .filter(|pl| {
+                let src = pl.src();
+                let relative_src = src.strip_prefix(&config.package).unwrap_or(src);
+                pattern.is_match(&relative_src.to_string_lossy())
+            })
+            .map(PlannedLink::dest)
+            .collect::<Vec<_>>();
+
+        if matching_dests.is_empty() {
+            continue;
+        }
+
+        if dry_run {
+            println!(
+                "would run trigger if '{}' changes: {}",
+                pattern.as_str().cyan(),
+                run.yellow()
+            );
+            continue;
+        }
+
+        run_hook_command("trigger", run, config, &join_links(&matching_dests)).map_err(|err| {
+            UnboxError::Trigger {
+                pattern: pattern.as_str().to_string(),
+                command: run.clone(),
+                source: err,
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs;
+
+    use anyhow::Context;
+
+    use crate::{
+        cli::{ExistingFileStrategy, UnboxCli},
+        plan::UnboxPlan,
+        test_utils::make_tmp_tree_with_target,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_run_hooks_env() -> anyhow::Result<()> {
+        let package = tempfile::tempdir().context("failed to make test package")?;
+        let package_path = package.path();
+        let mut config = PackageConfig::new_with_target(package_path, "/some/target");
+        config.hooks.post_unbox =
+            vec![r#"printf '%s|%s|%s|%s' "$BUB_HOOK" "$BUB_PACKAGE" "$BUB_TARGET" "$BUB_LINKS" > env.txt"#.to_string()];
+
+        run_hooks(HookStage::PostUnbox, &config, &["/a", "/b"], false)
+            .context("failed to run test hooks")?;
+
+        let env_str = fs::read_to_string(package_path.join("env.txt"))
+            .context("failed to read hook output")?;
+        assert_eq!(
+            env_str,
+            format!("post_unbox|{}|/some/target|/a\n/b", package_path.display())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_hooks_dry_run() -> anyhow::Result<()> {
+        let package = tempfile::tempdir().context("failed to make test package")?;
+        let package_path = package.path();
+        let mut config = PackageConfig::new(package_path);
+        config.hooks.pre_boxup = vec!["touch ran.txt".to_string()];
+
+        run_hooks::<&Path>(HookStage::PreBoxup, &config, &[], true)
+            .context("failed to run test hooks")?;
+
+        assert!(
+            !package_path.join("ran.txt").exists(),
+            "hook ran during dry run"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_hooks_failure() -> anyhow::Result<()> {
+        let package = tempfile::tempdir().context("failed to make test package")?;
+        let package_path = package.path();
+        let mut config = PackageConfig::new(package_path);
+        config.hooks.pre_unbox = vec!["exit 3".to_string(), "touch ran.txt".to_string()];
+
+        match run_hooks::<&Path>(HookStage::PreUnbox, &config, &[], false) {
+            Err(UnboxError::Hook {
+                stage,
+                command,
+                source: HookError::ExitStatus(status),
+            }) => {
+                assert_eq!(stage, HookStage::PreUnbox);
+                assert_eq!(command, "exit 3");
+                assert_eq!(status.code(), Some(3));
+            }
+            res => panic!("expected hook error, got {res:?}"),
+        }
+
+        assert!(
+            !package_path.join("ran.txt").exists(),
+            "hook ran after a failed hook"
+        );
+
+        Ok(())
+    }
+
+    /// Make a [`PlannedLink`] from `package` to `target` for each tail in `tails`.
+    fn make_links(package: &Path, target: &Path, tails: &[&str]) -> Vec<PlannedLink> {
+        tails
+            .iter()
+            .map(|tail| {
+                PlannedLink::new(
+                    package.join(tail),
+                    target.join(tail),
+                    crate::package::LinkType::default(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_run_triggers() -> anyhow::Result<()> {
+        let package = tempfile::tempdir().context("failed to make test package")?;
+        let package_path = package.path();
+        let target = Path::new("/some/target");
+        let mut config = PackageConfig::new_with_target(package_path, target);
+        config.triggers = vec![
+            Trigger {
+                pattern: Regex::new(r"^fonts/.*\.ttf$").context("failed to compile regex")?,
+                run: r#"printf '%s' "$BUB_LINKS" > fonts.txt"#.to_string(),
+            },
+            Trigger {
+                pattern: Regex::new(r"\.service$").context("failed to compile regex")?,
+                run: "touch services.txt".to_string(),
+            },
+        ];
+
+        let changed_links = make_links(
+            package_path,
+            target,
+            &["fonts/a.ttf", "fonts/b.otf", "fonts/c.ttf"],
+        );
+        run_triggers(&config, &changed_links, false).context("failed to run test triggers")?;
+
+        let fonts_str = fs::read_to_string(package_path.join("fonts.txt"))
+            .context("failed to read trigger output")?;
+        assert_eq!(
+            fonts_str,
+            "/some/target/fonts/a.ttf\n/some/target/fonts/c.ttf"
+        );
+        assert!(
+            !package_path.join("services.txt").exists(),
+            "trigger ran without matching links"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_triggers_unbox_twice() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let package =
+            make_tmp_tree_with_target(target.path()).context("failed to make test package")?;
+        let package_path = package.path();
+        let mut cli = UnboxCli::new(package_path);
+        cli.existing_file_strategy = Some(ExistingFileStrategy::Overwrite);
+        let mut config =
+            PackageConfig::init(package_path, &cli).context("failed to read test config")?;
+        config.triggers = vec![Trigger {
+            pattern: Regex::new(r"^test\.txt$").context("failed to compile regex")?,
+            run: "echo ran >> triggered.txt".to_string(),
+        }];
+        let plan = UnboxPlan::plan_unboxing(config.clone(), &cli)?;
+
+        // the second unboxing changes nothing, so the trigger only runs once
+        for _ in 0..2 {
+            let unboxed_links = plan.unbox().context("failed to unbox test package")?;
+            run_triggers(&config, &unboxed_links, false).context("failed to run test triggers")?;
+        }
+
+        let triggered_str = fs::read_to_string(package_path.join("triggered.txt"))
+            .context("failed to read trigger output")?;
+        assert_eq!(triggered_str, "ran\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use colored::Colorize;
+
+use crate::{
+    cli::UnboxCli,
+    error::ImportError,
+    manifest::UnboxManifest,
+    package::PackageConfig,
+    plan::{PlannedLink, UnboxPlan},
+    prompt::Prompt,
+    utils::{create_dir_all_tracked, move_path, replace_home_with_tilde},
+};
+
+/// A file or directory in a target that is imported into a package.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ImportedPath {
+    /// Where the path is in the target
+    pub origin: PathBuf,
+    /// Where the path goes in the package
+    pub package_path: PathBuf,
+}
+
+/// Plan importing `paths` into the package of `config`. Each path keeps its location relative to
+/// the target, e.g. `~/.config/nvim` is imported to `<package>/.config/nvim` if the target is
+/// `~`. Nothing is changed.
+///
+/// # Arguments
+///
+/// - `config` - Config of the package to import into.
+/// - `paths` - Absolute paths to import.
+///
+/// # Errors
+///
+/// An error is returned if the package links its root, or if a path isn't a real file or
+/// directory in the target, overlaps the package or another path, or already exists in the
+/// package.
+pub fn plan_import(
+    config: &PackageConfig,
+    paths: &[PathBuf],
+) -> Result<Vec<ImportedPath>, ImportError> {
+    if config.link_root {
+        return Err(ImportError::LinkRoot);
+    }
+    let io_err = |path: &Path| {
+        let path = path.to_path_buf();
+        |source| ImportError::Io { path, source }
+    };
+    let target = dunce::canonicalize(&config.target).map_err(io_err(&config.target))?;
+    // the package may not exist yet
+    let package = dunce::canonicalize(&config.package).unwrap_or_else(|_| config.package.clone());
+
+    let mut imports: Vec<ImportedPath> = Vec::with_capacity(paths.len());
+    for path in paths {
+        let md = path.symlink_metadata().map_err(io_err(path))?;
+        if md.is_symlink() {
+            return Err(ImportError::Symlink(path.clone()));
+        }
+        // resolve the parent, but not the path itself
+        let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+            return Err(ImportError::NotInTarget {
+                path: path.clone(),
+                target,
+            });
+        };
+        let origin = dunce::canonicalize(parent)
+            .map_err(io_err(parent))?
+            .join(file_name);
+        let Ok(tail) = origin.strip_prefix(&target) else {
+            return Err(ImportError::NotInTarget {
+                path: origin,
+                target,
+            });
+        };
+        if origin.starts_with(&package) || package.starts_with(&origin) {
+            return Err(ImportError::Overlapping(origin, package));
+        }
+        if let Some(other) = imports
+            .iter()
+            .find(|i| origin.starts_with(&i.origin) || i.origin.starts_with(&origin))
+        {
+            return Err(ImportError::Overlapping(origin, other.origin.clone()));
+        }
+        let package_path = package.join(tail);
+        if package_path.symlink_metadata().is_ok() {
+            return Err(ImportError::AlreadyInPackage(package_path));
+        }
+
+        imports.push(ImportedPath {
+            origin,
+            package_path,
+        });
+    }
+
+    Ok(imports)
+}
+
+/// Import files and directories into the package of `config` (see [`plan_import`]) and link
+/// them back. This is all or nothing: if anything fails, everything is moved back. The package
+/// directory is created if it doesn't exist, and `config` is saved to it if it doesn't have a
+/// config yet. Returns the links that were created.
+///
+/// # Arguments
+///
+/// - `config` - Config of the package to import into, already merged with `cli`.
+/// - `imports` - What to import.
+/// - `cli` - CLI flags to plan the links with.
+/// - `prompt` - Where to ask about existing target files (see [`UnboxPlan::unbox_prompted`]).
+///
+/// # Errors
+///
+/// An error is returned if a path cannot be moved, the package wouldn't link every imported file
+/// (e.g. because of its exclude patterns), or unboxing fails.
+pub fn import(
+    config: &PackageConfig,
+    imports: &[ImportedPath],
+    cli: &UnboxCli,
+    prompt: &mut dyn Prompt,
+) -> Result<Vec<PlannedLink>, ImportError> {
+    let package_dirs =
+        create_dir_all_tracked(&config.package).map_err(|source| ImportError::Io {
+            path: config.package.clone(),
+            source,
+        })?;
+    let had_config = config.disk_path().exists();
+    let mut moved = Vec::with_capacity(imports.len());
+    let result = __import_moved(config, imports, cli, prompt, &mut moved);
+    if result.is_err() {
+        __roll_back(&moved);
+        if !had_config {
+            let _ = fs::remove_file(config.disk_path());
+        }
+        for dir in package_dirs.iter().rev() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+
+    result
+}
+
+/// Move `imports` into the package, recording each one in `moved`, and link them back. This is
+/// the fallible part of [`import`].
+///
+/// # Arguments
+///
+/// - `config` - Config of the package to import into.
+/// - `imports` - What to import.
+/// - `cli` - CLI flags to plan the links with.
+/// - `prompt` - Where to ask about existing target files.
+/// - `moved` - Paths that were moved, for rolling back.
+///
+/// # Errors
+///
+/// See [`import`].
+fn __import_moved<'a>(
+    config: &PackageConfig,
+    imports: &'a [ImportedPath],
+    cli: &UnboxCli,
+    prompt: &mut dyn Prompt,
+    moved: &mut Vec<&'a ImportedPath>,
+) -> Result<Vec<PlannedLink>, ImportError> {
+    for imported in imports {
+        let ImportedPath {
+            origin,
+            package_path,
+        } = imported;
+        if let Some(parent) = package_path.parent() {
+            fs::create_dir_all(parent).map_err(|source| ImportError::Io {
+                path: parent.to_path_buf(),
+                source,
+            })?;
+        }
+        move_path(origin, package_path).map_err(|source| ImportError::Io {
+            path: origin.clone(),
+            source,
+        })?;
+        moved.push(imported);
+    }
+
+    let mut plan = UnboxPlan::plan_unboxing(config.clone(), cli)?;
+    plan.retain_links(|pl| imports.iter().any(|i| pl.dest().starts_with(&i.origin)));
+    let unlinked = __unlinked_files(imports, plan.links());
+    if !unlinked.is_empty() {
+        return Err(ImportError::NotLinked(unlinked));
+    }
+
+    if !config.disk_path().exists() {
+        config.save_to_package()?;
+    }
+
+    let mut manifest = UnboxManifest::load(config)?;
+    let unboxed_links = plan.unbox_prompted(&mut manifest, prompt)?;
+    manifest.share_dirs(&UnboxManifest::all_registered()?);
+    manifest.save()?;
+
+    Ok(unboxed_links)
+}
+
+/// Get the imported files that none of `links` would link back to their origin.
+///
+/// # Arguments
+///
+/// - `imports` - What was imported.
+/// - `links` - Links that were planned for the imports.
+///
+/// # Panics
+///
+/// This function will panic if a walked path isn't in its imported path, which shouldn't happen.
+fn __unlinked_files(imports: &[ImportedPath], links: &[PlannedLink]) -> Vec<PathBuf> {
+    imports
+        .iter()
+        .flat_map(|imported| {
+            walkdir::WalkDir::new(&imported.package_path)
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|entry| !entry.file_type().is_dir())
+                .map(|entry| {
+                    let tail = entry
+                        .path()
+                        .strip_prefix(&imported.package_path)
+                        .expect("walked entry should be prefixed by its root");
+                    // joining an empty tail would add a trailing slash
+                    if tail.as_os_str().is_empty() {
+                        imported.origin.clone()
+                    } else {
+                        imported.origin.join(tail)
+                    }
+                })
+        })
+        .filter(|dest| !links.iter().any(|pl| dest.starts_with(pl.dest())))
+        .collect()
+}
+
+/// Undo a failed [`import`] by removing any links at the origins of `moved` and moving them back,
+/// newest first. Failures are only warned about so as much as possible is restored.
+///
+/// # Arguments
+///
+/// - `moved` - Paths that were moved into the package.
+fn __roll_back(moved: &[&ImportedPath]) {
+    for ImportedPath {
+        origin,
+        package_path,
+    } in moved.iter().rev()
+    {
+        // anything at the origin now was created by unboxing, since the original was moved
+        let removed = match origin.symlink_metadata() {
+            Ok(md) if md.is_dir() => fs::remove_dir_all(origin),
+            Ok(_) => fs::remove_file(origin),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = removed.and_then(|()| move_path(package_path, origin)) {
+            eprintln!(
+                "{}: failed to move {} back to {}: {err}",
+                "warn".yellow(),
+                replace_home_with_tilde(package_path),
+                replace_home_with_tilde(origin)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use crate::prompt::NoPrompt;
+
+    use super::*;
+
+    #[test]
+    fn test_import() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = dunce::canonicalize(target.path())?;
+        let packages = tempfile::tempdir().context("failed to create temp packages dir")?;
+        let package_path = dunce::canonicalize(packages.path())?.join("new-package");
+
+        let rc_file = target_path.join(".testrc");
+        let config_dir = target_path.join(".config").join("app");
+        fs::write(&rc_file, "rc").context("failed to create test file")?;
+        fs::create_dir_all(config_dir.join("nested")).context("failed to create test dir")?;
+        fs::write(config_dir.join("nested").join("app.toml"), "app")
+            .context("failed to create test file")?;
+
+        let cli = UnboxCli::new(&package_path);
+        let mut config = PackageConfig::new(&package_path);
+        config.target.clone_from(&target_path);
+        let imports = plan_import(&config, &[rc_file.clone(), config_dir.clone()])
+            .context("failed to plan import")?;
+        assert_eq!(
+            imports[1].package_path,
+            package_path.join(".config").join("app")
+        );
+
+        let links = import(&config, &imports, &cli, &mut NoPrompt).context("failed to import")?;
+        assert_eq!(links.len(), 2);
+        assert_eq!(fs::read_link(&rc_file)?, package_path.join(".testrc"));
+        let app_toml = config_dir.join("nested").join("app.toml");
+        assert!(app_toml.is_symlink(), "{app_toml:?} was not linked");
+        assert_eq!(fs::read_to_string(&app_toml)?, "app");
+        let saved_config = PackageConfig::try_from_package(&package_path)
+            .context("import should save a config")?;
+        assert_eq!(saved_config.target, target_path);
+
+        // importing again is refused since it's a link now
+        let err = plan_import(&config, &[rc_file]).expect_err("imported a link");
+        assert!(matches!(err, ImportError::Symlink(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_rolls_back() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = dunce::canonicalize(target.path())?;
+        let packages = tempfile::tempdir().context("failed to create temp packages dir")?;
+        let package_path = dunce::canonicalize(packages.path())?.join("new-package");
+
+        // `.gitconfig` is excluded by default, so it wouldn't be linked back
+        let linked_file = target_path.join(".testrc");
+        let excluded_file = target_path.join(".gitconfig");
+        fs::write(&linked_file, "rc").context("failed to create test file")?;
+        fs::write(&excluded_file, "git").context("failed to create test file")?;
+
+        let cli = UnboxCli::new(&package_path);
+        let mut config = PackageConfig::new(&package_path);
+        config.target.clone_from(&target_path);
+        let imports = plan_import(&config, &[linked_file.clone(), excluded_file.clone()])
+            .context("failed to plan import")?;
+
+        let err =
+            import(&config, &imports, &cli, &mut NoPrompt).expect_err("imported an excluded file");
+        assert!(
+            matches!(err, ImportError::NotLinked(ref paths) if *paths == [excluded_file.clone()])
+        );
+        for (path, contents) in [(&linked_file, "rc"), (&excluded_file, "git")] {
+            assert!(!path.is_symlink(), "{path:?} was not moved back");
+            assert_eq!(fs::read_to_string(path)?, contents);
+        }
+        assert!(!package_path.exists(), "new package was not removed");
+
+        Ok(())
+    }
+}
@@ -1,11 +1,21 @@
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::too_many_lines)]
 
+pub mod backup;
+pub mod boxup;
 pub mod cli;
 pub mod constants;
+pub mod deps;
+pub mod diff;
+pub mod discovery;
 pub mod error;
+pub mod hooks;
+pub mod import;
+pub mod manifest;
 pub mod package;
 pub mod plan;
+pub mod prompt;
+pub mod trash;
 pub mod utils;
 
 mod test_utils;
@@ -1,16 +1,294 @@
 #![warn(clippy::all, clippy::pedantic)]
 
-use std::fs::OpenOptions;
-use std::io::Write;
 use std::path::Path;
 
 use anyhow::Context;
-use boxunbox::cli::{ColorOverride, UnboxCli};
+use boxunbox::backup::{Backup, collect_backups, forget_backups, format_age, select_prunable};
+use boxunbox::cli::{
+    BackupCommand, BackupListCli, BackupPruneCli, BackupRestoreCli, ColorOverride, ImportCli,
+    ListCli, UnboxCli, UnboxCommand,
+};
+use boxunbox::constants::BASE_DIRS;
+use boxunbox::deps::resolve_unbox_order;
+use boxunbox::diff::{colorize_diff, conflict_diff};
+use boxunbox::discovery::collect_packages;
 use boxunbox::error::UnboxError;
-use boxunbox::package::{self, PackageConfig};
-use boxunbox::plan::UnboxPlan;
+use boxunbox::hooks::{HookStage, run_hooks, run_triggers};
+use boxunbox::import::{import, plan_import};
+use boxunbox::manifest::UnboxManifest;
+use boxunbox::package::PackageConfig;
+use boxunbox::plan::{PlannedLink, UnboxPlan};
+use boxunbox::prompt::{TerminalPrompt, confirm};
 use boxunbox::utils::replace_home_with_tilde;
 use clap::Parser;
+use colored::Colorize;
+
+/// List packages with their resolved target, link type, and unboxed status.
+///
+/// # Arguments
+///
+/// - `list_cli` - Which packages to list.
+/// - `cli` - CLI flags to merge package configs with.
+fn list(list_cli: &ListCli, cli: &UnboxCli) -> anyhow::Result<()> {
+    let ListCli {
+        packages,
+        discovery,
+    } = list_cli;
+
+    let mut discovery = discovery.clone();
+    if packages.is_empty() && discovery.stow_dir.is_none() {
+        discovery.stow_dir = Some(std::env::current_dir()?);
+    }
+
+    let packages = collect_packages(packages, &discovery).context("failed to discover packages")?;
+    if packages.is_empty() {
+        eprintln!("{}: no packages found", "warn".yellow());
+    }
+
+    for package in packages {
+        let config = PackageConfig::load(&package, Some(cli)).with_context(|| {
+            format!(
+                "failed to read config for {}",
+                replace_home_with_tilde(&package)
+            )
+        })?;
+        let name = package.file_name().map_or_else(
+            || replace_home_with_tilde(&package),
+            |name| name.to_string_lossy().to_string(),
+        );
+        let status = if UnboxManifest::is_unboxed(&package) {
+            "unboxed".green()
+        } else {
+            "boxed".dimmed()
+        };
+        let link_root_note = if config.link_root { ", link root" } else { "" };
+
+        println!(
+            "{} -> {} ({}{link_root_note}) [{status}]",
+            name.bright_green(),
+            replace_home_with_tilde(&config.target).cyan(),
+            config.link_type,
+        );
+    }
+
+    Ok(())
+}
+
+/// Restore the newest matching backup of a file, or every matching backup in a backup run.
+///
+/// # Arguments
+///
+/// - `found_backups` - Backups to choose from.
+/// - `restore_cli` - Which backups to restore.
+fn restore_backups(found_backups: &[Backup], restore_cli: &BackupRestoreCli) -> anyhow::Result<()> {
+    let BackupRestoreCli {
+        origin,
+        run,
+        generation,
+        force,
+        ..
+    } = restore_cli;
+
+    let matching_backups = found_backups
+        .iter()
+        .filter(|b| origin.as_ref().is_none_or(|origin| b.origin == *origin))
+        .filter(|b| run.is_none() || b.run_name() == *run)
+        .filter(|b| generation.is_none_or(|g| b.generation == Some(g)))
+        .collect::<Vec<_>>();
+    // restore the newest backup of one file, or every backup in a run
+    let to_restore = if origin.is_some() {
+        matching_backups
+            .into_iter()
+            .max_by_key(|b| (b.timestamp, b.generation))
+            .into_iter()
+            .collect::<Vec<_>>()
+    } else {
+        matching_backups
+    };
+    if to_restore.is_empty() {
+        let what = origin.as_ref().map_or_else(
+            || format!("in run {}", run.as_deref().unwrap_or_default()),
+            |origin| format!("of {}", replace_home_with_tilde(origin)),
+        );
+        anyhow::bail!("no matching backup {what}");
+    }
+
+    let restore_count = to_restore.len();
+    let mut restored_paths = Vec::with_capacity(restore_count);
+    for backup in to_restore {
+        if let Err(err) = backup.restore(*force) {
+            eprintln!(
+                "{}: failed to restore {}: {err}",
+                "warn".yellow(),
+                replace_home_with_tilde(&backup.path)
+            );
+            continue;
+        }
+        println!(
+            "restored {} -> {}",
+            replace_home_with_tilde(&backup.path).yellow(),
+            replace_home_with_tilde(&backup.origin).cyan()
+        );
+        restored_paths.push(backup.path.clone());
+    }
+    forget_backups(&restored_paths).context("failed to update records")?;
+
+    let failed_count = restore_count - restored_paths.len();
+    if failed_count > 0 {
+        anyhow::bail!(
+            "{failed_count} backup(s) could not be restored, use --force to replace files that aren't links"
+        );
+    }
+
+    Ok(())
+}
+
+/// Manage backups made by unboxing.
+///
+/// # Arguments
+///
+/// - `command` - What to do with the backups.
+fn backups(command: &BackupCommand) -> anyhow::Result<()> {
+    let search = match command {
+        BackupCommand::List(BackupListCli { search })
+        | BackupCommand::Restore(BackupRestoreCli { search, .. })
+        | BackupCommand::Prune(BackupPruneCli { search, .. }) => search,
+    };
+    let search_dir = search
+        .target
+        .clone()
+        .unwrap_or_else(|| BASE_DIRS.home_dir().to_path_buf());
+    let found_backups = collect_backups(&search_dir).context("failed to find backups")?;
+
+    match command {
+        BackupCommand::List(_) => {
+            if found_backups.is_empty() {
+                eprintln!(
+                    "{}: no backups found in {}",
+                    "warn".yellow(),
+                    replace_home_with_tilde(&search_dir)
+                );
+            }
+
+            for backup in &found_backups {
+                let package_note = backup.package.as_ref().map_or_else(String::new, |package| {
+                    format!(" (moved by {})", replace_home_with_tilde(package))
+                });
+                let label = match (backup.generation, backup.run_name()) {
+                    (_, Some(run_name)) => format!("run {run_name}"),
+                    (Some(generation), None) => format!("generation {generation}"),
+                    (None, None) => "unknown".to_string(),
+                };
+                println!(
+                    "{} <- {} [{label}, {} old]{package_note}",
+                    replace_home_with_tilde(&backup.origin).cyan(),
+                    replace_home_with_tilde(&backup.path).yellow(),
+                    format_age(backup.age()),
+                );
+            }
+        }
+        BackupCommand::Restore(restore_cli) => restore_backups(&found_backups, restore_cli)?,
+        BackupCommand::Prune(BackupPruneCli {
+            keep,
+            older_than,
+            dry_run,
+            yes,
+            ..
+        }) => {
+            let prunable = select_prunable(&found_backups, *keep, *older_than);
+            if *dry_run {
+                for backup in &prunable {
+                    println!(
+                        "would delete {}",
+                        replace_home_with_tilde(&backup.path).red()
+                    );
+                }
+                eprintln!("dry run, not executing");
+                return Ok(());
+            }
+
+            if !prunable.is_empty() && !*yes {
+                for backup in &prunable {
+                    println!("{}", replace_home_with_tilde(&backup.path).red());
+                }
+                if !confirm(&mut TerminalPrompt, "Delete these backups?")
+                    .context("failed to confirm, use --yes to skip it")?
+                {
+                    println!("not pruning backups");
+                    return Ok(());
+                }
+            }
+
+            let mut pruned_paths = Vec::with_capacity(prunable.len());
+            for backup in &prunable {
+                if let Err(err) = backup.remove() {
+                    eprintln!(
+                        "{}: failed to delete {}: {err}",
+                        "warn".yellow(),
+                        replace_home_with_tilde(&backup.path)
+                    );
+                    continue;
+                }
+                println!("deleted {}", replace_home_with_tilde(&backup.path).red());
+                pruned_paths.push(&backup.path);
+            }
+            forget_backups(&pruned_paths).context("failed to update records")?;
+            println!("pruned {} backup(s)", pruned_paths.len());
+
+            let failed_count = prunable.len() - pruned_paths.len();
+            if failed_count > 0 {
+                anyhow::bail!("{failed_count} backup(s) could not be deleted");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Import existing files into a package and link them back.
+///
+/// # Arguments
+///
+/// - `import_cli` - What to import and where.
+/// - `cli` - CLI flags to merge the package config with.
+fn import_paths(import_cli: &ImportCli, cli: &UnboxCli) -> anyhow::Result<()> {
+    let ImportCli {
+        package,
+        paths,
+        dry_run,
+    } = import_cli;
+
+    let config = PackageConfig::load(package, Some(cli)).with_context(|| {
+        format!(
+            "failed to read config for {}",
+            replace_home_with_tilde(package)
+        )
+    })?;
+    let imports = plan_import(&config, paths).context("failed to plan import")?;
+    for imported in &imports {
+        println!(
+            "{} -> {}",
+            replace_home_with_tilde(&imported.origin).cyan(),
+            replace_home_with_tilde(&imported.package_path).bright_green()
+        );
+    }
+
+    if *dry_run {
+        eprintln!("dry run, not executing");
+        return Ok(());
+    }
+    let links = import(&config, &imports, cli, &mut TerminalPrompt)
+        .context("failed to import, nothing was changed")?;
+    let links_noun = if links.len() == 1 { "link" } else { "links" };
+    println!(
+        "Successfully imported {} path(s) into {} with {} {links_noun}!",
+        imports.len(),
+        replace_home_with_tilde(package),
+        links.len()
+    );
+
+    Ok(())
+}
 
 /// Unbox the package.
 ///
@@ -18,15 +296,7 @@ use clap::Parser;
 ///
 /// - `package` - Package directory to unbox.
 fn unbox(package: &Path, cli: &UnboxCli) -> Result<(), UnboxError> {
-    let config = match PackageConfig::init(package, cli) {
-        Ok(config) => config,
-        Err(package::error::ConfigRead::FileNotFound(_)) => {
-            let mut conf = PackageConfig::new(package);
-            conf.merge_with_cli(cli);
-            conf
-        }
-        Err(err) => return Err(err.into()),
-    };
+    let config = PackageConfig::load(package, Some(cli))?;
 
     #[cfg(debug_assertions)]
     println!("{config:#?}");
@@ -40,6 +310,8 @@ fn unbox(package: &Path, cli: &UnboxCli) -> Result<(), UnboxError> {
         config.save_to_package()?;
     }
 
+    run_hooks::<&Path>(HookStage::PreUnbox, &config, &[], cli.dry_run)?;
+
     let unboxing_plan = UnboxPlan::plan_unboxing(config.clone(), cli)?;
 
     #[cfg(debug_assertions)]
@@ -47,32 +319,47 @@ fn unbox(package: &Path, cli: &UnboxCli) -> Result<(), UnboxError> {
 
     println!("{}", unboxing_plan.display(&config));
 
+    if cli.diff {
+        for pl in unboxing_plan.conflicts() {
+            print!("{}", colorize_diff(&conflict_diff(pl.dest(), pl.src())));
+        }
+    }
+
     if cli.dry_run {
         eprintln!("dry run, not executing");
+        run_hooks::<&Path>(HookStage::PostUnbox, &config, &[], cli.dry_run)?;
+        // links that are already unboxed wouldn't change
+        let changing_links = unboxing_plan
+            .links()
+            .iter()
+            .filter(|pl| !pl.is_unboxed())
+            .cloned()
+            .collect::<Vec<_>>();
+        run_triggers(&config, &changing_links, cli.dry_run)?;
     } else {
-        let unboxed_links = unboxing_plan.unbox()?;
+        let mut manifest = UnboxManifest::load(&config)?;
+        let unbox_result = unboxing_plan.unbox_prompted(&mut manifest, &mut TerminalPrompt);
+        // directories created by other packages must outlive them if this package uses them
+        manifest.share_dirs(&UnboxManifest::all_registered()?);
+        // save the manifest even if unboxing failed partway so the links that were created can
+        // still be boxed up
+        if !manifest.records.is_empty() {
+            manifest.save()?;
+        }
+        let unboxed_links = unbox_result?;
         let links_noun = if unboxed_links.len() == 1 {
             "link"
         } else {
             "links"
         };
         println!("Successfully unboxed {} {links_noun}!", unboxed_links.len());
-        // TODO: store list of unboxed files in cache file for boxing up later
-        let result_file = config.package.join(".bub.last");
-        let mut rfd = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&result_file)
-            .map_err(|err| UnboxError::Io {
-                path: result_file.clone(),
-                source: err,
-            })?;
-        for link in &unboxed_links {
-            writeln!(rfd, "{}", link.dest().display()).map_err(|err| UnboxError::Io {
-                path: result_file.clone(),
-                source: err,
-            })?;
-        }
+
+        let unboxed_dests = unboxed_links
+            .iter()
+            .map(PlannedLink::dest)
+            .collect::<Vec<_>>();
+        run_hooks(HookStage::PostUnbox, &config, &unboxed_dests, false)?;
+        run_triggers(&config, &unboxed_links, false)?;
     }
 
     Ok(())
@@ -86,6 +373,8 @@ fn main() -> anyhow::Result<()> {
 
     let UnboxCli {
         ref packages,
+        ref command,
+        ref discovery,
         color_override,
         ..
     } = cli;
@@ -96,14 +385,39 @@ fn main() -> anyhow::Result<()> {
         ColorOverride::Never => colored::control::set_override(false),
     }
 
-    for package in packages {
-        let canon_package = dunce::canonicalize(package)?;
-        unbox(&canon_package, &cli).with_context(|| {
-            format!(
-                "failed to unbox {}",
-                replace_home_with_tilde(&canon_package)
-            )
-        })?;
+    match command {
+        Some(UnboxCommand::List(list_cli)) => return list(list_cli, &cli),
+        Some(UnboxCommand::Backups(backup_command)) => return backups(backup_command),
+        Some(UnboxCommand::Import(import_cli)) => return import_paths(import_cli, &cli),
+        None => {}
+    }
+
+    let packages = collect_packages(packages, discovery).context("failed to discover packages")?;
+    if packages.is_empty() {
+        anyhow::bail!("no packages to unbox");
+    }
+
+    let packages = packages
+        .iter()
+        .map(dunce::canonicalize)
+        .collect::<Result<Vec<_>, _>>()?;
+    let unbox_order =
+        resolve_unbox_order(&packages).context("failed to resolve package requirements")?;
+
+    // required packages use their own configs, not the overrides meant for the requested ones
+    let required_cli = cli.without_config_overrides();
+    for package in unbox_order {
+        let package_cli = if packages.contains(&package) {
+            &cli
+        } else {
+            println!(
+                "unboxing required package {}",
+                replace_home_with_tilde(&package).bright_green()
+            );
+            &required_cli
+        };
+        unbox(&package, package_cli)
+            .with_context(|| format!("failed to unbox {}", replace_home_with_tilde(&package)))?;
     }
 
     Ok(())
@@ -0,0 +1,1013 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::{LAST_UNBOXING_FILE_NAME, MANIFEST_FILE_NAME, STATE_DIR},
+    error::ManifestError,
+    package::{LinkType, PackageConfig},
+    plan::PlannedLink,
+    utils::{atomic_write, fnv1a_64, git_revision, replace_home_with_tilde, unix_timestamp},
+};
+
+/// Current version of the [`UnboxManifest`] format.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Describes what happened when a link was unboxed.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkOutcome {
+    /// Nothing existed at the link path, so the link was created.
+    Created,
+    /// The existing file was adopted into the package.
+    Adopted,
+    /// The existing file was moved to a backup.
+    Moved,
+    /// The existing file was overwritten.
+    Overwritten,
+    /// The existing file was moved to the trash.
+    Trashed,
+    /// The existing file was identical to, or a link to, the package file, so it was replaced.
+    Replaced,
+    /// The record was migrated from an old `.bub.last` file, so the outcome is unknown.
+    Migrated,
+    /// The link already existed, so nothing was changed.
+    Unchanged,
+}
+
+/// A record of one unboxed link.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LinkRecord {
+    /// Path in the package the link points to.
+    pub src: PathBuf,
+    /// Path of the link in the target.
+    pub dest: PathBuf,
+    /// Type of link that was created.
+    pub link_type: LinkType,
+    /// What happened when the link was unboxed.
+    pub outcome: LinkOutcome,
+    /// Where the existing file was moved to, if it was moved.
+    #[serde(default)]
+    pub backup: Option<PathBuf>,
+    /// When the link was unboxed, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Git revision of the package when the link was unboxed, if it is in a git repository.
+    #[serde(default)]
+    pub revision: Option<String>,
+}
+
+/// Describes where an [`UnboxManifest`] is stored.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StateLocation {
+    /// In the per-user state registry (see [`crate::constants::STATE_DIR`]), keyed by the
+    /// package and target. This works with read-only packages and keeps the package clean.
+    #[default]
+    Registry,
+    /// In the package directory itself, as `.bub.manifest.json`.
+    Package,
+}
+
+/// A versioned record of everything unboxed from a package to a target. This is stored as JSON,
+/// either in the state registry or in the package directory (see [`StateLocation`]), and
+/// replaces the old, append-only `.bub.last` file.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct UnboxManifest {
+    /// Version of the manifest format.
+    pub version: u32,
+    /// Package the manifest is for.
+    pub package: PathBuf,
+    /// Target the package was unboxed to.
+    #[serde(default)]
+    pub target: PathBuf,
+    /// Path this manifest is stored at.
+    #[serde(skip)]
+    path: PathBuf,
+    /// Git revision of the package, used for new records.
+    #[serde(skip)]
+    revision: Option<String>,
+    /// One record per unboxed link, unique by [`LinkRecord::dest`].
+    pub records: Vec<LinkRecord>,
+    /// Directories created by unboxing that this manifest holds a reference to. They are removed
+    /// when boxing up, once they are empty and no other manifest references them.
+    #[serde(default)]
+    pub created_dirs: Vec<PathBuf>,
+}
+
+impl Display for LinkOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            LinkOutcome::Created => "created",
+            LinkOutcome::Adopted => "adopted",
+            LinkOutcome::Moved => "moved",
+            LinkOutcome::Overwritten => "overwritten",
+            LinkOutcome::Trashed => "trashed",
+            LinkOutcome::Replaced => "replaced",
+            LinkOutcome::Migrated => "migrated",
+            LinkOutcome::Unchanged => "unchanged",
+        };
+
+        write!(f, "{s}")
+    }
+}
+
+impl From<&LinkRecord> for PlannedLink {
+    fn from(value: &LinkRecord) -> Self {
+        PlannedLink::new(value.src.clone(), value.dest.clone(), value.link_type)
+    }
+}
+
+impl UnboxManifest {
+    /// Create a new, empty [`UnboxManifest`] for the package and target of a [`PackageConfig`].
+    /// New records will not have a revision; use [`UnboxManifest::load`] for that.
+    ///
+    /// # Arguments
+    ///
+    /// - `config` - Config of the package the manifest is for.
+    #[must_use]
+    pub fn new(config: &PackageConfig) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            package: config.package.clone(),
+            target: config.target.clone(),
+            path: Self::path_for(config),
+            revision: None,
+            records: Vec::new(),
+            created_dirs: Vec::new(),
+        }
+    }
+
+    /// Get the directory of the state registry, where manifests are stored by default.
+    #[must_use]
+    pub fn registry_dir() -> PathBuf {
+        STATE_DIR.join("manifests")
+    }
+
+    /// Get the path of the manifest file for the package and target of a [`PackageConfig`],
+    /// depending on its [`PackageConfig::state_location`].
+    ///
+    /// # Arguments
+    ///
+    /// - `config` - Package config.
+    #[must_use]
+    pub fn path_for(config: &PackageConfig) -> PathBuf {
+        let PackageConfig {
+            package,
+            target,
+            state_location,
+            ..
+        } = config;
+
+        match state_location {
+            StateLocation::Registry => {
+                let mut key = package.as_os_str().as_encoded_bytes().to_vec();
+                key.push(0);
+                key.extend_from_slice(target.as_os_str().as_encoded_bytes());
+                let name = package
+                    .file_name()
+                    .map_or_else(|| "root".into(), |name| name.to_string_lossy());
+                Self::registry_dir().join(format!("{name}-{:016x}.json", fnv1a_64(&key)))
+            }
+            StateLocation::Package => package.join(MANIFEST_FILE_NAME),
+        }
+    }
+
+    /// Get the path this manifest is stored at.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read a manifest from a file.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - Manifest file to read.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the file cannot be read or parsed, or if its version is newer than
+    /// [`MANIFEST_VERSION`].
+    fn read_from(path: &Path) -> Result<Self, ManifestError> {
+        let manifest_str = fs::read_to_string(path).map_err(|err| ManifestError::Io {
+            path: path.to_path_buf(),
+            source: err,
+        })?;
+        let mut manifest: Self = serde_json::from_str(&manifest_str)?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(ManifestError::UnsupportedVersion(manifest.version));
+        }
+        manifest.version = MANIFEST_VERSION;
+        manifest.path = path.to_path_buf();
+
+        Ok(manifest)
+    }
+
+    /// Get the directory where in-package manifests are registered, so they can be found without
+    /// knowing their package (see [`UnboxManifest::all_registered`]).
+    fn package_manifests_dir() -> PathBuf {
+        STATE_DIR.join("package_manifests")
+    }
+
+    /// Get the path of the registry entry for the in-package manifest at `manifest_path`. The
+    /// entry is a JSON string holding `manifest_path`.
+    ///
+    /// # Arguments
+    ///
+    /// - `manifest_path` - Path of an in-package manifest.
+    fn pointer_path(manifest_path: &Path) -> PathBuf {
+        let name = manifest_path
+            .parent()
+            .and_then(Path::file_name)
+            .map_or_else(|| "root".into(), |name| name.to_string_lossy());
+        let key = manifest_path.as_os_str().as_encoded_bytes();
+        Self::package_manifests_dir().join(format!("{name}-{:016x}.json", fnv1a_64(key)))
+    }
+
+    /// Returns `true` if this manifest is stored in its package instead of the state registry.
+    fn is_in_package(&self) -> bool {
+        !self.path.starts_with(Self::registry_dir())
+    }
+
+    /// Remove the registry entry for the in-package manifest at `manifest_path`, if there is one.
+    ///
+    /// # Arguments
+    ///
+    /// - `manifest_path` - Path of an in-package manifest.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the entry exists but cannot be removed.
+    fn __unregister(manifest_path: &Path) -> Result<(), ManifestError> {
+        let pointer_path = Self::pointer_path(manifest_path);
+        match fs::remove_file(&pointer_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(ManifestError::Io {
+                path: pointer_path,
+                source: err,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get every JSON file in `dir`, or nothing if `dir` doesn't exist.
+    ///
+    /// # Arguments
+    ///
+    /// - `dir` - Directory to read.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if `dir` exists but cannot be read.
+    fn __json_files_in(dir: &Path) -> Result<Vec<PathBuf>, ManifestError> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(ManifestError::Io {
+                    path: dir.to_path_buf(),
+                    source: err,
+                });
+            }
+        };
+
+        let mut paths = Vec::new();
+        for res in entries {
+            let entry = res.map_err(|err| ManifestError::Io {
+                path: dir.to_path_buf(),
+                source: err,
+            })?;
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                paths.push(path);
+            }
+        }
+
+        Ok(paths)
+    }
+
+    /// Read every manifest in the state registry, including in-package manifests registered
+    /// there when they were saved. Manifests that cannot be read or parsed are skipped with a
+    /// warning so one corrupt manifest doesn't hide the others. In-package manifests that no
+    /// longer exist (e.g. their package was deleted) are skipped silently.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the registry cannot be read.
+    pub fn all_registered() -> Result<Vec<Self>, ManifestError> {
+        let mut manifest_paths = Self::__json_files_in(&Self::registry_dir())?;
+        for pointer_path in Self::__json_files_in(&Self::package_manifests_dir())? {
+            let pointer = fs::read_to_string(&pointer_path)
+                .map_err(|err| ManifestError::Io {
+                    path: pointer_path.clone(),
+                    source: err,
+                })
+                .and_then(|pointer_str| Ok(serde_json::from_str::<PathBuf>(&pointer_str)?));
+            match pointer {
+                Ok(path) if path.exists() => manifest_paths.push(path),
+                Ok(_) => {}
+                Err(err) => eprintln!(
+                    "{}: skipping unreadable manifest entry {}: {err}",
+                    "warn".yellow(),
+                    replace_home_with_tilde(&pointer_path)
+                ),
+            }
+        }
+
+        let mut manifests = Vec::new();
+        for path in manifest_paths {
+            match Self::read_from(&path) {
+                Ok(manifest) => manifests.push(manifest),
+                Err(err) => eprintln!(
+                    "{}: skipping unreadable manifest {}: {err}",
+                    "warn".yellow(),
+                    replace_home_with_tilde(&path)
+                ),
+            }
+        }
+
+        manifests.sort_by(|a, b| a.path.cmp(&b.path));
+        manifests.dedup_by(|a, b| a.path == b.path);
+        Ok(manifests)
+    }
+
+    /// Returns `true` if the package has a manifest anywhere (or an old `.bub.last` file),
+    /// meaning it is currently unboxed.
+    ///
+    /// # Arguments
+    ///
+    /// - `package` - Package directory.
+    pub fn is_unboxed<P: AsRef<Path>>(package: P) -> bool {
+        let registered_packages = Self::all_registered()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|m| m.package)
+            .collect();
+        Self::is_unboxed_in(package, &registered_packages)
+    }
+
+    /// Returns `true` if the package is currently unboxed, like [`UnboxManifest::is_unboxed`],
+    /// but checks the packages of already read [`UnboxManifest::all_registered`] manifests
+    /// instead of reading the state registry again.
+    ///
+    /// # Arguments
+    ///
+    /// - `package` - Package directory.
+    /// - `registered_packages` - Packages of every manifest in the state registry.
+    pub fn is_unboxed_in<P: AsRef<Path>>(
+        package: P,
+        registered_packages: &HashSet<PathBuf>,
+    ) -> bool {
+        let package = package.as_ref();
+        package.join(MANIFEST_FILE_NAME).exists()
+            || package.join(LAST_UNBOXING_FILE_NAME).exists()
+            || registered_packages.contains(package)
+    }
+
+    /// Load the manifest for the package and target of a [`PackageConfig`]. If there is no
+    /// manifest, but there is an old one (an in-package manifest when using the state registry,
+    /// or a `.bub.last` file), it is migrated to a new manifest, which is saved, and the old file
+    /// is removed. If there is neither, a new, empty manifest is returned. Either way, new
+    /// records will be tagged with the current git revision of the package.
+    ///
+    /// # Arguments
+    ///
+    /// - `config` - Config of the package to load the manifest for.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the manifest cannot be read or parsed, or if migrating the old
+    /// file fails.
+    pub fn load(config: &PackageConfig) -> Result<Self, ManifestError> {
+        let mut manifest = Self::__inner_load(config)?;
+        manifest.revision = git_revision(&config.package);
+        Ok(manifest)
+    }
+
+    /// Utility function for [`UnboxManifest::load`] that reads, migrates, or creates the manifest.
+    ///
+    /// # Arguments
+    ///
+    /// - `config` - Config of the package to load the manifest for.
+    fn __inner_load(config: &PackageConfig) -> Result<Self, ManifestError> {
+        let package = &config.package;
+        let manifest_path = Self::path_for(config);
+
+        if manifest_path.exists() {
+            return Self::read_from(&manifest_path);
+        }
+
+        let package_manifest_path = package.join(MANIFEST_FILE_NAME);
+        let last_unboxing_file = package.join(LAST_UNBOXING_FILE_NAME);
+        if package_manifest_path != manifest_path && package_manifest_path.exists() {
+            let mut manifest = Self::read_from(&package_manifest_path)?;
+            manifest.path = manifest_path;
+            manifest.target.clone_from(&config.target);
+            manifest.save()?;
+            Self::remove_migrated(&package_manifest_path, &manifest.path)?;
+            Ok(manifest)
+        } else if last_unboxing_file.exists() {
+            Self::migrate_last_file(config, &last_unboxing_file)
+        } else {
+            Ok(Self::new(config))
+        }
+    }
+
+    /// Load every manifest for the package of a [`PackageConfig`], regardless of target. This
+    /// includes manifests in the state registry, an in-package manifest, and an old `.bub.last`
+    /// file, which is migrated.
+    ///
+    /// # Arguments
+    ///
+    /// - `config` - Config of the package to load manifests for.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if any manifest cannot be read or parsed, or if migrating an old file
+    /// fails.
+    pub fn load_all(config: &PackageConfig) -> Result<Vec<Self>, ManifestError> {
+        let package = &config.package;
+        let mut manifests = Self::all_registered()?
+            .into_iter()
+            .filter(|m| m.package == *package)
+            .collect::<Vec<_>>();
+
+        let package_manifest_path = package.join(MANIFEST_FILE_NAME);
+        if package.join(LAST_UNBOXING_FILE_NAME).exists() {
+            let migrated = Self::load(config)?;
+            manifests.retain(|m| m.path != migrated.path);
+            manifests.push(migrated);
+        } else if package_manifest_path.exists()
+            && !manifests.iter().any(|m| m.path == package_manifest_path)
+        {
+            manifests.push(Self::read_from(&package_manifest_path)?);
+        }
+
+        Ok(manifests)
+    }
+
+    /// Remove an old manifest file after it has been migrated and print a warning.
+    ///
+    /// # Arguments
+    ///
+    /// - `old_path` - Old file that was migrated.
+    /// - `new_path` - New manifest file.
+    fn remove_migrated(old_path: &Path, new_path: &Path) -> Result<(), ManifestError> {
+        fs::remove_file(old_path).map_err(|err| ManifestError::Io {
+            path: old_path.to_path_buf(),
+            source: err,
+        })?;
+        Self::__unregister(old_path)?;
+        eprintln!(
+            "{}: migrated {} to {}",
+            "warn".yellow(),
+            replace_home_with_tilde(old_path),
+            replace_home_with_tilde(new_path)
+        );
+
+        Ok(())
+    }
+
+    /// Migrate an old `.bub.last` file, which only lists link paths, to a new manifest. The
+    /// source and link type of each link are inferred from the link itself. Paths that no longer
+    /// exist are dropped since there's nothing to box up.
+    ///
+    /// # Arguments
+    ///
+    /// - `config` - Config of the package the file belongs to.
+    /// - `last_unboxing_file` - Path to the `.bub.last` file.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the old file cannot be read or removed, or the new manifest cannot
+    /// be saved.
+    fn migrate_last_file(
+        config: &PackageConfig,
+        last_unboxing_file: &Path,
+    ) -> Result<Self, ManifestError> {
+        let PackageConfig {
+            package, target, ..
+        } = config;
+
+        let last_str = fs::read_to_string(last_unboxing_file).map_err(|err| ManifestError::Io {
+            path: last_unboxing_file.to_path_buf(),
+            source: err,
+        })?;
+
+        let mut manifest = Self::new(config);
+        for dest in last_str
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(PathBuf::from)
+        {
+            let Ok(md) = dest.symlink_metadata() else {
+                continue;
+            };
+
+            let (src, link_type) = if md.is_symlink() {
+                let Ok(link_target) = fs::read_link(&dest) else {
+                    continue;
+                };
+                if link_target.is_absolute() {
+                    (link_target, LinkType::SymlinkAbsolute)
+                } else {
+                    let dest_parent = dest.parent().unwrap_or(&dest);
+                    (
+                        path_clean::clean(dest_parent.join(link_target)),
+                        LinkType::SymlinkRelative,
+                    )
+                }
+            } else if let Ok(tail) = dest.strip_prefix(target) {
+                (package.join(tail), LinkType::HardLink)
+            } else {
+                eprintln!(
+                    "{}: cannot infer the source of {}, dropping it from the manifest",
+                    "warn".yellow(),
+                    replace_home_with_tilde(&dest)
+                );
+                continue;
+            };
+
+            manifest.push(LinkRecord {
+                src,
+                dest,
+                link_type,
+                outcome: LinkOutcome::Migrated,
+                backup: None,
+                timestamp: unix_timestamp(),
+                revision: None,
+            });
+        }
+
+        manifest.save()?;
+        Self::remove_migrated(last_unboxing_file, &manifest.path)?;
+
+        Ok(manifest)
+    }
+
+    /// Atomically save this manifest to [`UnboxManifest::path`], creating parent directories as
+    /// needed. In-package manifests are also registered in the state registry so
+    /// [`UnboxManifest::all_registered`] can find them.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the manifest cannot be serialized or written.
+    pub fn save(&self) -> Result<(), ManifestError> {
+        let manifest_path = &self.path;
+        let manifest_str = serde_json::to_string_pretty(self)?;
+        if let Some(parent) = manifest_path.parent() {
+            fs::create_dir_all(parent).map_err(|err| ManifestError::Io {
+                path: parent.to_path_buf(),
+                source: err,
+            })?;
+        }
+        atomic_write(manifest_path, manifest_str).map_err(|err| ManifestError::Io {
+            path: manifest_path.clone(),
+            source: err,
+        })?;
+
+        if self.is_in_package() {
+            let pointer_path = Self::pointer_path(manifest_path);
+            let pointer_str = serde_json::to_string(manifest_path)?;
+            fs::create_dir_all(Self::package_manifests_dir())
+                .and_then(|()| atomic_write(&pointer_path, pointer_str))
+                .map_err(|err| ManifestError::Io {
+                    path: pointer_path,
+                    source: err,
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the manifest file at [`UnboxManifest::path`], and its registry entry if it is an
+    /// in-package manifest.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the file or its registry entry cannot be removed.
+    pub fn remove(&self) -> Result<(), ManifestError> {
+        fs::remove_file(&self.path).map_err(|err| ManifestError::Io {
+            path: self.path.clone(),
+            source: err,
+        })?;
+
+        if self.is_in_package() {
+            Self::__unregister(&self.path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add a record to this manifest, replacing any existing record with the same
+    /// [`LinkRecord::dest`].
+    ///
+    /// # Arguments
+    ///
+    /// - `record` - Record to add.
+    pub fn push(&mut self, record: LinkRecord) {
+        self.records.retain(|r| r.dest != record.dest);
+        self.records.push(record);
+    }
+
+    /// Record a newly unboxed [`PlannedLink`] with the current time and package revision.
+    ///
+    /// # Arguments
+    ///
+    /// - `pl` - The unboxed link.
+    /// - `outcome` - What happened when it was unboxed.
+    /// - `backup` - Where the existing file was moved to, if anywhere.
+    pub fn record(&mut self, pl: &PlannedLink, outcome: LinkOutcome, backup: Option<PathBuf>) {
+        self.push(LinkRecord {
+            src: pl.src().to_path_buf(),
+            dest: pl.dest().to_path_buf(),
+            link_type: pl.ty(),
+            outcome,
+            backup,
+            timestamp: unix_timestamp(),
+            revision: self.revision.clone(),
+        });
+    }
+
+    /// Record directories that were created by unboxing.
+    ///
+    /// # Arguments
+    ///
+    /// - `dirs` - Created directories.
+    pub fn record_dirs<I: IntoIterator<Item = PathBuf>>(&mut self, dirs: I) {
+        for dir in dirs {
+            if !self.created_dirs.contains(&dir) {
+                self.created_dirs.push(dir);
+            }
+        }
+    }
+
+    /// Take a reference to every directory created by another manifest that one of this
+    /// manifest's links is in, so that directory outlives the other manifest if it is boxed up
+    /// first.
+    ///
+    /// # Arguments
+    ///
+    /// - `others` - Other manifests, usually [`UnboxManifest::all_registered`]. This manifest is
+    ///   skipped if it is one of them.
+    pub fn share_dirs(&mut self, others: &[Self]) {
+        let shared_dirs = others
+            .iter()
+            .filter(|other| other.path != self.path)
+            .flat_map(|other| &other.created_dirs)
+            .filter(|dir| self.records.iter().any(|r| r.dest.starts_with(dir)))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.record_dirs(shared_dirs);
+    }
+
+    /// Get the directories this manifest created (or shares) that no other manifest references,
+    /// deepest first so they can be removed in order.
+    ///
+    /// # Arguments
+    ///
+    /// - `others` - Other manifests, usually [`UnboxManifest::all_registered`]. This manifest is
+    ///   skipped if it is one of them.
+    #[must_use]
+    pub fn unshared_dirs(&self, others: &[Self]) -> Vec<PathBuf> {
+        let mut dirs = self
+            .created_dirs
+            .iter()
+            .filter(|dir| {
+                !others
+                    .iter()
+                    .filter(|other| other.path != self.path)
+                    .any(|other| other.created_dirs.contains(dir))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        dirs
+    }
+
+    /// Get the link paths of every record, sorted.
+    #[must_use]
+    pub fn dests(&self) -> Vec<PathBuf> {
+        let mut dests = self
+            .records
+            .iter()
+            .map(|r| r.dest.clone())
+            .collect::<Vec<_>>();
+        dests.sort();
+        dests
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use crate::{test_utils::make_tmp_tree, utils::os_symlink};
+
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let config = PackageConfig::new(package_path);
+
+        let mut manifest = UnboxManifest::new(&config);
+        let pl = PlannedLink::new(
+            package_path.join("test.txt"),
+            "/some/target/test.txt",
+            LinkType::SymlinkAbsolute,
+        );
+        manifest.record(&pl, LinkOutcome::Created, None);
+        // re-recording the same dest replaces the old record
+        manifest.record(
+            &pl,
+            LinkOutcome::Moved,
+            Some("/some/target/test.txt.bak0".into()),
+        );
+        manifest.save().context("failed to save manifest")?;
+
+        let loaded = UnboxManifest::load(&config).context("failed to load manifest")?;
+        assert_eq!(loaded.records.len(), 1);
+        assert_eq!(loaded.records, manifest.records);
+        assert_eq!(loaded.records[0].outcome, LinkOutcome::Moved);
+        assert!(UnboxManifest::is_unboxed(package_path));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_registered_skips_corrupt() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let manifest = UnboxManifest::new(&PackageConfig::new(package_path));
+        manifest.save().context("failed to save manifest")?;
+        fs::write(
+            UnboxManifest::registry_dir().join("corrupt.json"),
+            "{ not json",
+        )
+        .context("failed to write corrupt manifest")?;
+
+        let registered = UnboxManifest::all_registered().context("failed to read registry")?;
+        assert_eq!(registered.len(), 1);
+        assert_eq!(registered[0].package, package_path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_missing() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let config = PackageConfig::new(package_path);
+
+        let manifest = UnboxManifest::load(&config).context("failed to load manifest")?;
+        assert!(manifest.records.is_empty());
+        assert!(!UnboxManifest::is_unboxed(package_path));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_last_file() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let config = PackageConfig::new_with_target(package_path, target_path);
+
+        let symlink_src = package_path.join("test.txt");
+        let symlink_dest = target_path.join("test.txt");
+        os_symlink(&symlink_src, &symlink_dest).context("failed to create test symlink")?;
+        let hard_link_src = package_path.join("test_ignore.txt");
+        let hard_link_dest = target_path.join("test_ignore.txt");
+        fs::hard_link(&hard_link_src, &hard_link_dest)
+            .context("failed to create test hard link")?;
+        let missing_dest = target_path.join("missing.txt");
+
+        let last_unboxing_file = package_path.join(LAST_UNBOXING_FILE_NAME);
+        // duplicates were common in old files since they were append-only
+        fs::write(
+            &last_unboxing_file,
+            format!(
+                "{}\n{}\n{}\n{}\n",
+                symlink_dest.display(),
+                hard_link_dest.display(),
+                missing_dest.display(),
+                symlink_dest.display()
+            ),
+        )
+        .context("failed to write test last unboxing file")?;
+
+        let manifest = UnboxManifest::load(&config).context("failed to migrate manifest")?;
+        assert!(
+            !last_unboxing_file.exists(),
+            "old last unboxing file was not removed"
+        );
+        assert!(
+            UnboxManifest::path_for(&config).exists(),
+            "migrated manifest was not saved"
+        );
+
+        let actual = manifest
+            .records
+            .iter()
+            .map(|r| (r.src.clone(), r.dest.clone(), r.link_type, r.outcome))
+            .collect::<Vec<_>>();
+        let expected = vec![
+            (
+                hard_link_src,
+                hard_link_dest,
+                LinkType::HardLink,
+                LinkOutcome::Migrated,
+            ),
+            (
+                symlink_src,
+                symlink_dest,
+                LinkType::SymlinkAbsolute,
+                LinkOutcome::Migrated,
+            ),
+        ];
+        assert_eq!(actual, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_separate_targets() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let config_a = PackageConfig::new_with_target(package_path, "/some/target/a");
+        let config_b = PackageConfig::new_with_target(package_path, "/some/target/b");
+        assert_ne!(
+            UnboxManifest::path_for(&config_a),
+            UnboxManifest::path_for(&config_b)
+        );
+
+        for config in [&config_a, &config_b] {
+            let mut manifest = UnboxManifest::load(config).context("failed to load manifest")?;
+            let pl = PlannedLink::new(
+                package_path.join("test.txt"),
+                config.target.join("test.txt"),
+                LinkType::SymlinkAbsolute,
+            );
+            manifest.record(&pl, LinkOutcome::Created, None);
+            manifest.save().context("failed to save manifest")?;
+        }
+
+        let mut targets = UnboxManifest::load_all(&config_a)
+            .context("failed to load all manifests")?
+            .into_iter()
+            .map(|m| m.target)
+            .collect::<Vec<_>>();
+        targets.sort();
+        assert_eq!(targets, vec![config_a.target, config_b.target]);
+        assert!(
+            !package_path.join(MANIFEST_FILE_NAME).exists(),
+            "manifest was written to the package"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_package_manifest() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let mut config = PackageConfig::new(package_path);
+        config.state_location = StateLocation::Package;
+
+        let mut manifest = UnboxManifest::new(&config);
+        let pl = PlannedLink::new(
+            package_path.join("test.txt"),
+            config.target.join("test.txt"),
+            LinkType::SymlinkAbsolute,
+        );
+        manifest.record(&pl, LinkOutcome::Created, None);
+        manifest.save().context("failed to save manifest")?;
+        let package_manifest_path = package_path.join(MANIFEST_FILE_NAME);
+        assert_eq!(manifest.path(), package_manifest_path);
+
+        config.state_location = StateLocation::Registry;
+        let migrated = UnboxManifest::load(&config).context("failed to migrate manifest")?;
+        assert!(
+            !package_manifest_path.exists(),
+            "old package manifest was not removed"
+        );
+        assert_eq!(migrated.path(), UnboxManifest::path_for(&config));
+        assert_eq!(migrated.records, manifest.records);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_dirs() -> anyhow::Result<()> {
+        let package_a = make_tmp_tree().context("failed to make test package")?;
+        let package_b = make_tmp_tree().context("failed to make test package")?;
+        let config_a = PackageConfig::new(package_a.path());
+        let config_b = PackageConfig::new(package_b.path());
+        let shared_dir = config_a.target.join(".config");
+        let nested_dir = shared_dir.join("a");
+
+        let mut manifest_a = UnboxManifest::new(&config_a);
+        manifest_a.record(
+            &PlannedLink::new(
+                package_a.path().join("test.txt"),
+                nested_dir.join("test.txt"),
+                LinkType::SymlinkAbsolute,
+            ),
+            LinkOutcome::Created,
+            None,
+        );
+        manifest_a.record_dirs([shared_dir.clone(), nested_dir.clone()]);
+
+        // b is unboxed into the dir a created
+        let mut manifest_b = UnboxManifest::new(&config_b);
+        manifest_b.record(
+            &PlannedLink::new(
+                package_b.path().join("test.txt"),
+                shared_dir.join("test.txt"),
+                LinkType::SymlinkAbsolute,
+            ),
+            LinkOutcome::Created,
+            None,
+        );
+        manifest_b.share_dirs(std::slice::from_ref(&manifest_a));
+        assert_eq!(manifest_b.created_dirs, std::slice::from_ref(&shared_dir));
+
+        let manifests = [manifest_a.clone(), manifest_b.clone()];
+        assert_eq!(manifest_a.unshared_dirs(&manifests), [nested_dir]);
+        // once a is boxed up, b is the only one left referencing the shared dir
+        assert_eq!(
+            manifest_b.unshared_dirs(std::slice::from_ref(&manifest_b)),
+            [shared_dir]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_dirs_package_manifest() -> anyhow::Result<()> {
+        let package_a = make_tmp_tree().context("failed to make test package")?;
+        let package_b = make_tmp_tree().context("failed to make test package")?;
+        let config_a = PackageConfig::new(package_a.path());
+        let mut config_b = PackageConfig::new(package_b.path());
+        config_b.state_location = StateLocation::Package;
+        let shared_dir = config_a.target.join(".config");
+
+        let mut manifest_a = UnboxManifest::new(&config_a);
+        manifest_a.record(
+            &PlannedLink::new(
+                package_a.path().join("test.txt"),
+                shared_dir.join("a.txt"),
+                LinkType::SymlinkAbsolute,
+            ),
+            LinkOutcome::Created,
+            None,
+        );
+        manifest_a.record_dirs([shared_dir.clone()]);
+        manifest_a
+            .save()
+            .context("failed to save registry manifest")?;
+
+        // b keeps its manifest in the package, but is unboxed into the dir a created
+        let mut manifest_b = UnboxManifest::new(&config_b);
+        manifest_b.record(
+            &PlannedLink::new(
+                package_b.path().join("test.txt"),
+                shared_dir.join("b.txt"),
+                LinkType::SymlinkAbsolute,
+            ),
+            LinkOutcome::Created,
+            None,
+        );
+        manifest_b.share_dirs(&UnboxManifest::all_registered()?);
+        assert_eq!(manifest_b.created_dirs, std::slice::from_ref(&shared_dir));
+        manifest_b
+            .save()
+            .context("failed to save package manifest")?;
+
+        let registered = UnboxManifest::all_registered()?;
+        assert_eq!(registered.len(), 2);
+        assert!(registered.iter().any(|m| m.path() == manifest_b.path()));
+        assert!(
+            manifest_a.unshared_dirs(&registered).is_empty(),
+            "dir shared with a package manifest would be removed"
+        );
+
+        // once b is boxed up, a is the only one left referencing the shared dir
+        manifest_b
+            .remove()
+            .context("failed to remove package manifest")?;
+        let registered = UnboxManifest::all_registered()?;
+        assert_eq!(registered, [manifest_a.clone()]);
+        assert_eq!(manifest_a.unshared_dirs(&registered), [shared_dir]);
+
+        Ok(())
+    }
+}
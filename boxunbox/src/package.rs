@@ -13,8 +13,10 @@ use regex::Regex;
 use serde::{Deserialize, Deserializer, Serialize, de::Error};
 
 use crate::{
-    cli::UnboxCli,
+    cli::{ExistingFileStrategy, UnboxCli},
     constants::BASE_DIRS,
+    hooks::{Hooks, Trigger},
+    manifest::StateLocation,
     utils::{expand_into_pathbuf, replace_home_with_tilde},
 };
 
@@ -34,6 +36,22 @@ where
     expand_into_pathbuf(s).map_err(D::Error::custom)
 }
 
+/// Utility function to deserialize a [`Vec`] of [`PathBuf`]s while expanding environment variables
+/// and `~` in each one.
+///
+/// # Arguments
+///
+/// - `d` - Argument to deserialize, expected to be `Vec<String>`.
+fn __de_pathbuf_vec<'de, D>(d: D) -> Result<Vec<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let v: Vec<String> = Deserialize::deserialize(d)?;
+    v.into_iter()
+        .map(|s| expand_into_pathbuf(s).map_err(D::Error::custom))
+        .collect()
+}
+
 /// Utility function returning the default value for [`PackageConfig::exclude_pats`], which is a
 /// Regex for the config file, `git` files, and some `.md` files.
 fn __exclude_pats_default() -> Vec<Regex> {
@@ -65,10 +83,13 @@ fn __target_default() -> PathBuf {
 }
 
 /// Describes what type of link to create.
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize, ValueEnum)]
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize, ValueEnum,
+)]
 #[cfg_attr(test, derive(Hash))]
 pub enum LinkType {
     /// A soft link (symlink) pointing to an absolute path.
+    #[default]
     #[serde(rename = "absolute")]
     #[value(name = "absolute")]
     SymlinkAbsolute,
@@ -80,6 +101,27 @@ pub enum LinkType {
     #[serde(rename = "hard")]
     #[value(name = "hard")]
     HardLink,
+    /// A copy of the file. This is only created in place of a [`LinkType::HardLink`] that
+    /// [`HardLinkFallback::Copy`] applies to.
+    #[serde(rename = "copy")]
+    #[value(skip)]
+    Copy,
+}
+
+/// What to do instead of creating a [`LinkType::HardLink`] if the package file and target are on
+/// different filesystems, where hard links are impossible.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize, ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum HardLinkFallback {
+    /// Throw an error while planning.
+    #[default]
+    Error,
+    /// Create an absolute symlink instead.
+    Symlink,
+    /// Copy the file instead.
+    Copy,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -125,15 +167,58 @@ pub struct PackageConfig {
     /// Only link the root package folder, creating one link.
     #[serde(default = "bool::default")]
     pub link_root: bool,
-    /// What type of link to create.
+    /// What type of link to create, unless a rule in [`Self::link_type_rules`] matches the file.
     #[serde(default = "LinkType::default")]
     pub link_type: LinkType,
+    /// What type of link to create for specific files. The first matching rule is used.
+    #[serde(default = "Vec::default")]
+    pub link_type_rules: Vec<LinkTypeRule>,
+    /// What to do if a file would be hard linked across filesystems.
+    #[serde(default = "HardLinkFallback::default")]
+    pub hard_link_fallback: HardLinkFallback,
+    /// Packages this package requires. Relative paths are relative to [`Self::package`]. Required
+    /// packages are unboxed before this one.
+    #[serde(default = "Vec::default", deserialize_with = "__de_pathbuf_vec")]
+    pub requires: Vec<PathBuf>,
+    /// Where to store the record of what was unboxed.
+    #[serde(default = "StateLocation::default")]
+    pub state_location: StateLocation,
+    /// What to do if a target file already exists, unless a rule in
+    /// [`Self::existing_file_rules`] matches it.
+    #[serde(default = "ExistingFileStrategy::default", rename = "if_exists")]
+    pub existing_file_strategy: ExistingFileStrategy,
+    /// What to do if specific target files already exist. The first matching rule is used.
+    #[serde(default = "Vec::default", rename = "if_exists_rules")]
+    pub existing_file_rules: Vec<ExistingFileRule>,
+    /// Commands to run only when matching links change.
+    #[serde(default = "Vec::default")]
+    pub triggers: Vec<Trigger>,
+    /// Commands to run before and after (un)boxing.
+    #[serde(default = "Hooks::default")]
+    pub hooks: Hooks,
 }
 
-impl Default for LinkType {
-    fn default() -> Self {
-        Self::SymlinkAbsolute
-    }
+/// What to do if the target files of links matching [`ExistingFileRule::pattern`] already exist,
+/// instead of [`PackageConfig::existing_file_strategy`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExistingFileRule {
+    /// [`Regex`] matched against the path of each link's source, relative to the package.
+    #[serde(with = "serde_regex")]
+    pub pattern: Regex,
+    /// What to do if a matching target file already exists.
+    #[serde(rename = "if_exists")]
+    pub strategy: ExistingFileStrategy,
+}
+
+/// What type of link to create for files matching [`LinkTypeRule::pattern`], instead of
+/// [`PackageConfig::link_type`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LinkTypeRule {
+    /// [`Regex`] matched against the path of each file, relative to the package.
+    #[serde(with = "serde_regex")]
+    pub pattern: Regex,
+    /// What type of link to create for a matching file.
+    pub link_type: LinkType,
 }
 
 #[cfg(test)]
@@ -156,6 +241,18 @@ impl Display for LinkType {
             LinkType::SymlinkAbsolute => "absolute symlink",
             LinkType::SymlinkRelative => "relative symlink",
             LinkType::HardLink => "hard link",
+            LinkType::Copy => "copy",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl Display for HardLinkFallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            HardLinkFallback::Error => "error",
+            HardLinkFallback::Symlink => "symlink",
+            HardLinkFallback::Copy => "copy",
         };
         write!(f, "{s}")
     }
@@ -193,6 +290,31 @@ impl PartialEq for PackageConfig {
                 .all(|s| other_include_pats.contains(&s))
             && self.link_root == other.link_root
             && self.link_type == other.link_type
+            && self.link_type_rules.len() == other.link_type_rules.len()
+            && self
+                .link_type_rules
+                .iter()
+                .zip(&other.link_type_rules)
+                .all(|(l, r)| {
+                    l.pattern.as_str() == r.pattern.as_str() && l.link_type == r.link_type
+                })
+            && self.hard_link_fallback == other.hard_link_fallback
+            && self.requires == other.requires
+            && self.state_location == other.state_location
+            && self.hooks == other.hooks
+            && self.existing_file_strategy == other.existing_file_strategy
+            && self.existing_file_rules.len() == other.existing_file_rules.len()
+            && self
+                .existing_file_rules
+                .iter()
+                .zip(&other.existing_file_rules)
+                .all(|(l, r)| l.pattern.as_str() == r.pattern.as_str() && l.strategy == r.strategy)
+            && self.triggers.len() == other.triggers.len()
+            && self
+                .triggers
+                .iter()
+                .zip(&other.triggers)
+                .all(|(l, r)| l.pattern.as_str() == r.pattern.as_str() && l.run == r.run)
     }
 }
 
@@ -323,6 +445,14 @@ impl PackageConfig {
             include_pats: Vec::default(),
             link_root: bool::default(),
             link_type: LinkType::default(),
+            link_type_rules: Vec::default(),
+            hard_link_fallback: HardLinkFallback::default(),
+            requires: Vec::default(),
+            state_location: StateLocation::default(),
+            existing_file_strategy: ExistingFileStrategy::default(),
+            existing_file_rules: Vec::default(),
+            triggers: Vec::default(),
+            hooks: Hooks::default(),
         }
     }
 
@@ -342,6 +472,14 @@ impl PackageConfig {
             include_pats: Vec::default(),
             link_root: bool::default(),
             link_type: LinkType::default(),
+            link_type_rules: Vec::default(),
+            hard_link_fallback: HardLinkFallback::default(),
+            requires: Vec::default(),
+            state_location: StateLocation::default(),
+            existing_file_strategy: ExistingFileStrategy::default(),
+            existing_file_rules: Vec::default(),
+            triggers: Vec::default(),
+            hooks: Hooks::default(),
         }
     }
 
@@ -423,6 +561,55 @@ impl PackageConfig {
         Ok(config)
     }
 
+    /// Load the config for a `package`, falling back to a default config if the package doesn't
+    /// have one. If `cli` flags are given, the config is read with [`Self::init`] and merged with
+    /// them either way.
+    ///
+    /// # Arguments
+    ///
+    /// - `package` - Package directory to load the config from.
+    /// - `cli` - CLI flags to merge the config with, if any.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the package has a config file that cannot be read or parsed.
+    pub fn load<P: Into<PathBuf>>(
+        package: P,
+        cli: Option<&UnboxCli>,
+    ) -> Result<Self, error::ConfigRead> {
+        let package = package.into();
+        let config = match cli {
+            Some(cli) => Self::init(&package, cli),
+            None => Self::try_from_package(&package),
+        };
+        match config {
+            Ok(config) => Ok(config),
+            Err(error::ConfigRead::FileNotFound(_)) => {
+                let mut config = Self::new(package);
+                if let Some(cli) = cli {
+                    config.merge_with_cli(cli);
+                }
+                Ok(config)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Get the type of link to create for the package file at `relative_src`, which is that of the
+    /// first rule in [`Self::link_type_rules`] matching it, or [`Self::link_type`] if none do.
+    ///
+    /// # Arguments
+    ///
+    /// - `relative_src` - Path of the package file, relative to [`Self::package`].
+    #[must_use]
+    pub fn link_type_for<P: AsRef<Path>>(&self, relative_src: P) -> LinkType {
+        let relative_src = relative_src.as_ref().to_string_lossy();
+        self.link_type_rules
+            .iter()
+            .find(|rule| rule.pattern.is_match(&relative_src))
+            .map_or(self.link_type, |rule| rule.link_type)
+    }
+
     /// Merge fields from a given [`UnboxCli`] with this [`PackageConfig`]. The CLI fields are
     /// given precedence and will overwrite the config fields when prudent. [`Vec`] fields, such as
     /// [`Self::exclude_pats`], are extended with the CLI values instead of being overwritten
@@ -438,9 +625,15 @@ impl PackageConfig {
         if let Some(link_type) = cli.link_type {
             self.link_type = link_type;
         }
+        if let Some(fallback) = cli.hard_link_fallback {
+            self.hard_link_fallback = fallback;
+        }
         if let Some(target) = cli.target.as_ref() {
             self.target.clone_from(target);
         }
+        if let Some(efs) = cli.existing_file_strategy {
+            self.existing_file_strategy = efs;
+        }
     }
 
     /// Create a [`PackageConfig`] from an [`OldPackageConfig`]. This is kept for backwards
@@ -477,12 +670,20 @@ impl PackageConfig {
                 (false, false) => LinkType::SymlinkAbsolute,
                 (true, false) => LinkType::SymlinkRelative,
             },
+            link_type_rules: Vec::default(),
+            hard_link_fallback: HardLinkFallback::default(),
+            requires: Vec::default(),
+            state_location: StateLocation::default(),
+            existing_file_strategy: ExistingFileStrategy::default(),
+            existing_file_rules: Vec::default(),
+            triggers: Vec::default(),
+            hooks: Hooks::default(),
         }
     }
 
     /// Get the disk path for this `PackageConfig`.
     #[inline]
-    fn disk_path(&self) -> PathBuf {
+    pub(crate) fn disk_path(&self) -> PathBuf {
         self.package.join(Self::__serde_file_name())
     }
 
@@ -656,6 +857,45 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_init_without_config_overrides() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let mut cli = UnboxCli::new(package_path);
+        cli.target = Some(PathBuf::from("/path/to/test/target"));
+        cli.link_type = Some(LinkType::HardLink);
+        cli.dry_run = true;
+
+        let required_cli = cli.without_config_overrides();
+        assert!(required_cli.dry_run);
+        let conf = PackageConfig::init(package_path, &required_cli)
+            .context("failed to create package config from package")?;
+        let saved_conf = PackageConfig::try_from_package(package_path)
+            .context("failed to read test package config")?;
+        assert_eq!(conf.target, saved_conf.target);
+        assert_eq!(conf.link_type, saved_conf.link_type);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_without_config() -> anyhow::Result<()> {
+        let package = tempfile::tempdir().context("failed to make empty test package")?;
+        let package_path = package.path();
+        let expected_target = PathBuf::from("/path/to/test/target");
+        let mut cli = UnboxCli::new(package_path);
+        cli.target = Some(expected_target.clone());
+
+        let conf = PackageConfig::load(package_path, None)
+            .context("failed to load default package config")?;
+        assert_eq!(conf, PackageConfig::new(package_path));
+        let conf = PackageConfig::load(package_path, Some(&cli))
+            .context("failed to load default package config with cli")?;
+        assert_eq!(conf.target, expected_target);
+
+        Ok(())
+    }
+
     #[test]
     fn test_save_to_package() -> anyhow::Result<()> {
         let package = tempfile::tempdir().context("failed to make test package")?;
@@ -1,17 +1,26 @@
 use std::{
     fmt::Display,
-    fs, io,
+    fs, io, iter,
     path::{Path, PathBuf},
 };
 
-use colored::Colorize;
+use colored::{ColoredString, Colorize};
 use pathdiff::diff_paths;
 
 use crate::{
+    backup::{BackupRun, stash},
     cli::{ExistingFileStrategy, UnboxCli},
+    constants::TRASH_DIR,
+    diff::{colorize_diff, conflict_diff},
     error::{PlanningError, UnboxError},
-    package::{LinkType, PackageConfig, error::ConfigRead},
-    utils::{generate_backup_file_name, os_symlink, replace_home_with_tilde},
+    manifest::{LinkOutcome, UnboxManifest},
+    package::{ExistingFileRule, HardLinkFallback, LinkType, PackageConfig, error::ConfigRead},
+    prompt::{NoPrompt, Prompt},
+    trash::trash,
+    utils::{
+        create_dir_all_tracked, generate_backup_file_name, git_has_uncommitted_changes,
+        has_same_contents, is_same_file, mount_point, os_symlink, replace_home_with_tilde,
+    },
 };
 
 pub struct DisplayPlan<'a> {
@@ -27,12 +36,47 @@ pub struct PlannedLink {
     ty: LinkType,
 }
 
+/// What to do if [`PlannedLink::dest`]s planned with a nested [`PackageConfig`] exist, which is
+/// used instead of [`UnboxPlan::efs`] and [`UnboxPlan::efs_rules`] for them
+#[derive(Debug)]
+struct NestedEfs {
+    /// Directory of the nested config, which [`NestedEfs::efs_rules`] are relative to
+    package: PathBuf,
+    /// What to do if [`PlannedLink::dest`] exists and no rule in [`NestedEfs::efs_rules`]
+    /// matches it
+    efs: ExistingFileStrategy,
+    /// What to do if specific [`PlannedLink::dest`]s exist
+    efs_rules: Vec<ExistingFileRule>,
+}
+
 #[derive(Debug)]
 pub struct UnboxPlan {
     /// Planned links
     links: Vec<PlannedLink>,
-    /// What to do if [`PlannedLink::dest`] exists
+    /// What to do if [`PlannedLink::dest`] exists and no rule in [`UnboxPlan::efs_rules`]
+    /// matches it
     efs: ExistingFileStrategy,
+    /// What to do if specific [`PlannedLink::dest`]s exist (see [`UnboxPlan::efs_for`])
+    efs_rules: Vec<ExistingFileRule>,
+    /// Package the links are from, which [`UnboxPlan::efs_rules`] are relative to
+    package: PathBuf,
+    /// Target the package is unboxed to, which stashed files keep their paths relative to
+    target: PathBuf,
+    /// Strategies of nested configs, which override the ones above for their links
+    nested_efs: Vec<NestedEfs>,
+    /// What to do if [`UnboxPlan::efs`] is [`ExistingFileStrategy::Ask`], but the user can't be
+    /// asked
+    ask_fallback: ExistingFileStrategy,
+    /// Whether to replace a [`PlannedLink::dest`] that is identical to its [`PlannedLink::src`]
+    /// regardless of [`UnboxPlan::efs`] (see [`PlannedLink::has_identical_dest`])
+    replace_identical: bool,
+    /// Whether [`ExistingFileStrategy::Adopt`] follows a [`PlannedLink::dest`] that is a symlink
+    /// and adopts the file it points to
+    adopt_symlinks: bool,
+    /// [`PlannedLink::dest`]s that can't be hard linked to their [`PlannedLink::src`] because
+    /// they're on different filesystems, so they're planned with
+    /// [`PackageConfig::hard_link_fallback`] instead
+    hard_link_fallbacks: Vec<PathBuf>,
 
     #[cfg(debug_assertions)]
     /// Whether to create missing dirs in `target` or not
@@ -46,8 +90,15 @@ impl Display for DisplayPlan<'_> {
         let UnboxPlan {
             links,
             efs,
+            efs_rules,
+            nested_efs,
+            ask_fallback,
+            replace_identical,
+            adopt_symlinks,
+            hard_link_fallbacks,
             #[cfg(debug_assertions)]
             create_dirs,
+            ..
         } = plan;
 
         let PackageConfig {
@@ -82,6 +133,9 @@ impl Display for DisplayPlan<'_> {
         let mut links = links.clone();
         links.sort_by_key(|pl| pl.dest.clone());
 
+        let mut conflict_count = 0_usize;
+        let mut identical_count = 0_usize;
+        let mut unboxed_count = 0_usize;
         for pl in &links {
             let PlannedLink { src, dest, ty } = pl;
             let formatted_dest = path_formatter(dest, target);
@@ -89,7 +143,7 @@ impl Display for DisplayPlan<'_> {
 
             match ty {
                 LinkType::SymlinkAbsolute => {
-                    writeln!(
+                    write!(
                         f,
                         "{} -> {}",
                         formatted_dest.cyan(),
@@ -98,36 +152,161 @@ impl Display for DisplayPlan<'_> {
                 }
                 LinkType::SymlinkRelative => {
                     let relative_src = pl.get_src_relative_to_dest();
-                    writeln!(
+                    write!(
                         f,
                         "{} -> {}",
                         formatted_dest.cyan(),
                         relative_src.display().to_string().bright_green(),
                     )?;
                 }
-                LinkType::HardLink => {
-                    writeln!(
+                LinkType::HardLink | LinkType::Copy => {
+                    write!(
                         f,
                         "{} ({}) -> {}",
                         formatted_dest.cyan(),
-                        "hard link".bright_red(),
+                        ty.to_string().bright_red(),
                         formatted_src.bright_green(),
                     )?;
                 }
             }
+            if hard_link_fallbacks.contains(dest) {
+                write!(f, " {}", "(different filesystem)".yellow())?;
+            }
+
+            if pl.is_unboxed() {
+                unboxed_count += 1;
+                write!(f, " {}", "(already unboxed)".green())?;
+            } else if dest.symlink_metadata().is_ok() {
+                if *replace_identical && pl.has_identical_dest() {
+                    identical_count += 1;
+                    write!(f, " {}", "(identical, will be replaced)".green())?;
+                } else {
+                    conflict_count += 1;
+                    let link_efs = plan.efs_for(src);
+                    if efs_rules.is_empty() && nested_efs.is_empty() {
+                        writeln!(f, " {}", "(exists)".yellow())?;
+                    } else {
+                        writeln!(f, " {}", format!("(exists, {link_efs})").yellow())?;
+                    }
+                    if matches!(link_efs, ExistingFileStrategy::Adopt)
+                        && (*adopt_symlinks || !dest.is_symlink())
+                    {
+                        __fmt_adopted_changes(f, pl, package)?;
+                    }
+                    continue;
+                }
+            }
+            writeln!(f)?;
         }
 
-        write!(f, "If a target file already exists, it will ")?;
+        if conflict_count > 0 {
+            writeln!(
+                f,
+                "{}",
+                format!("{conflict_count} target file(s) already exist").yellow()
+            )?;
+        }
+        let (copied_count, symlinked_count) = links
+            .iter()
+            .filter(|pl| hard_link_fallbacks.contains(&pl.dest))
+            .fold((0_usize, 0_usize), |(copied, symlinked), pl| {
+                if pl.ty == LinkType::Copy {
+                    (copied + 1, symlinked)
+                } else {
+                    (copied, symlinked + 1)
+                }
+            });
+        for (count, verb) in [(copied_count, "copied"), (symlinked_count, "symlinked")] {
+            if count > 0 {
+                writeln!(
+                    f,
+                    "{}",
+                    format!(
+                        "{count} file(s) can't be hard linked across filesystems and will be {verb} instead"
+                    )
+                    .yellow()
+                )?;
+            }
+        }
+        if unboxed_count > 0 {
+            writeln!(
+                f,
+                "{}",
+                format!("{unboxed_count} link(s) are already unboxed and will be left alone")
+                    .green()
+            )?;
+        }
+        if identical_count > 0 {
+            writeln!(
+                f,
+                "{}",
+                format!(
+                    "{identical_count} target file(s) are identical to the package and will be replaced"
+                )
+                .green()
+            )?;
+        }
 
-        let efs_verb = match efs {
-            ExistingFileStrategy::Adopt => "be adopted".green(),
-            ExistingFileStrategy::Ignore => "be ignored".cyan(),
-            ExistingFileStrategy::Move => "be moved to <target_file>.bak#".yellow(),
-            ExistingFileStrategy::Overwrite => "be overwritten".bright_red(),
-            ExistingFileStrategy::ThrowError => "throw an error".bright_red(),
+        for ExistingFileRule { pattern, strategy } in efs_rules {
+            writeln!(
+                f,
+                "If a target file matching '{}' already exists, it will {}",
+                pattern.as_str().cyan(),
+                __efs_verb(*strategy, *ask_fallback, *adopt_symlinks)
+            )?;
+        }
+        for NestedEfs {
+            package: nested_package,
+            efs: nested_efs,
+            efs_rules: nested_rules,
+        } in nested_efs
+        {
+            let formatted_package = path_formatter(nested_package, package);
+            for ExistingFileRule { pattern, strategy } in nested_rules {
+                writeln!(
+                    f,
+                    "If a target file matching '{}' in '{}' already exists, it will {}",
+                    pattern.as_str().cyan(),
+                    formatted_package.bright_green(),
+                    __efs_verb(*strategy, *ask_fallback, *adopt_symlinks)
+                )?;
+            }
+            let subject = if nested_rules.is_empty() {
+                "a target file"
+            } else {
+                "any other target file"
+            };
+            writeln!(
+                f,
+                "If {subject} in '{}' already exists, it will {}",
+                formatted_package.bright_green(),
+                __efs_verb(*nested_efs, *ask_fallback, *adopt_symlinks)
+            )?;
+        }
+        let subject = if efs_rules.is_empty() && nested_efs.is_empty() {
+            "a target file"
+        } else {
+            "any other target file"
         };
-
-        writeln!(f, "{efs_verb}")?;
+        writeln!(
+            f,
+            "If {subject} already exists, it will {}",
+            __efs_verb(*efs, *ask_fallback, *adopt_symlinks)
+        )?;
+        let adopts = iter::once(*efs)
+            .chain(efs_rules.iter().map(|rule| rule.strategy))
+            .chain(nested_efs.iter().flat_map(|nested| {
+                iter::once(nested.efs).chain(nested.efs_rules.iter().map(|rule| rule.strategy))
+            }))
+            .any(|efs| matches!(efs, ExistingFileStrategy::Adopt));
+        if adopts {
+            writeln!(
+                f,
+                "Adopting overwrites package files marked {} (they're backed up first unless committed to git) and creates ones marked {}",
+                "~".yellow(),
+                "+".green()
+            )?;
+        }
 
         #[cfg(debug_assertions)]
         {
@@ -145,12 +324,90 @@ impl Display for DisplayPlan<'_> {
     }
 }
 
+/// Describe what happens to an existing target file with `efs` for a [`DisplayPlan`], e.g. "be
+/// overwritten".
+///
+/// # Arguments
+///
+/// - `efs` - Strategy to describe.
+/// - `ask_fallback` - Fallback strategy if `efs` is [`ExistingFileStrategy::Ask`].
+/// - `adopt_symlinks` - Whether [`ExistingFileStrategy::Adopt`] follows symlinks.
+fn __efs_verb(
+    efs: ExistingFileStrategy,
+    ask_fallback: ExistingFileStrategy,
+    adopt_symlinks: bool,
+) -> ColoredString {
+    match efs {
+        ExistingFileStrategy::Ask => {
+            format!("be asked about (or use '{ask_fallback}' without a terminal)").cyan()
+        }
+        ExistingFileStrategy::Adopt if adopt_symlinks => "be adopted (following symlinks)".green(),
+        ExistingFileStrategy::Adopt => "be adopted".green(),
+        ExistingFileStrategy::Ignore => "be ignored".cyan(),
+        ExistingFileStrategy::Move => "be moved to <target_file>.bak#".yellow(),
+        ExistingFileStrategy::Stash => format!(
+            "be moved to {}/<run>/<target_file>",
+            replace_home_with_tilde(BackupRun::runs_dir())
+        )
+        .yellow(),
+        ExistingFileStrategy::Overwrite => "be overwritten".bright_red(),
+        ExistingFileStrategy::Trash => {
+            format!("be moved to {}", replace_home_with_tilde(&*TRASH_DIR)).yellow()
+        }
+        ExistingFileStrategy::ThrowError => "throw an error".bright_red(),
+    }
+}
+
+/// Write the package files that adopting [`PlannedLink::dest`] would change for a [`DisplayPlan`],
+/// one per line. New files are marked with `+` and overwritten files with `~`. Nothing is written
+/// if there's nothing to adopt or it can't be read.
+///
+/// # Arguments
+///
+/// - `f` - Formatter to write to.
+/// - `pl` - Link whose `dest` would be adopted.
+/// - `package` - Package being unboxed.
+///
+/// # Errors
+///
+/// An error is returned if writing fails.
+fn __fmt_adopted_changes(
+    f: &mut std::fmt::Formatter<'_>,
+    pl: &PlannedLink,
+    package: &Path,
+) -> std::fmt::Result {
+    let Ok(Some(adopted)) = __adopted_path(pl, Some(package)) else {
+        return Ok(());
+    };
+    for (path, exists) in __adopted_changes(&adopted, &pl.src).unwrap_or_default() {
+        let formatted_path = path.strip_prefix(package).map_or_else(
+            |_| replace_home_with_tilde(&path),
+            |stripped| stripped.to_string_lossy().to_string(),
+        );
+        if exists {
+            writeln!(f, "  {} {}", "~".yellow(), formatted_path.yellow())?;
+        } else {
+            writeln!(f, "  {} {}", "+".green(), formatted_path.green())?;
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 impl<A: Into<PlannedLink>> FromIterator<A> for UnboxPlan {
     fn from_iter<T: IntoIterator<Item = A>>(iter: T) -> Self {
         Self {
             links: iter.into_iter().map(Into::into).collect(),
             efs: ExistingFileStrategy::default(),
+            efs_rules: Vec::new(),
+            package: PathBuf::new(),
+            target: PathBuf::new(),
+            nested_efs: Vec::new(),
+            ask_fallback: ExistingFileStrategy::default(),
+            replace_identical: true,
+            adopt_symlinks: false,
+            hard_link_fallbacks: Vec::new(),
 
             #[cfg(debug_assertions)]
             create_dirs: true,
@@ -159,11 +416,165 @@ impl<A: Into<PlannedLink>> FromIterator<A> for UnboxPlan {
 }
 
 impl PlannedLink {
+    /// Create a new [`PlannedLink`] where `dest` will point to `src`.
+    ///
+    /// # Arguments
+    ///
+    /// - `src` - Path in the package.
+    /// - `dest` - Path of the link in the target.
+    /// - `ty` - Type of link to create.
+    #[must_use]
+    pub fn new<P: Into<PathBuf>, Q: Into<PathBuf>>(src: P, dest: Q, ty: LinkType) -> Self {
+        Self {
+            src: src.into(),
+            dest: dest.into(),
+            ty,
+        }
+    }
+
     #[must_use]
     pub fn dest(&self) -> &'_ Path {
         &self.dest
     }
 
+    #[must_use]
+    pub fn src(&self) -> &'_ Path {
+        &self.src
+    }
+
+    #[must_use]
+    pub fn ty(&self) -> LinkType {
+        self.ty
+    }
+
+    /// Box up this [`PlannedLink`] by removing the link at [`Self::dest`]. Symlinks are removed
+    /// without being followed, so links to directories (e.g. from `link_root` packages or folded
+    /// directories) are removed instead of skipped, and their contents are left untouched.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if [`Self::dest`] does not exist, if it is a real directory, or if
+    /// it cannot be removed.
+    pub fn boxup(&self) -> io::Result<()> {
+        let dest = &self.dest;
+        let md = dest.symlink_metadata()?;
+
+        if md.is_dir() {
+            // only real directories get here since the metadata is of the link itself
+            Err(io::ErrorKind::IsADirectory.into())
+        } else if md.is_symlink() {
+            // Windows distinguishes directory symlinks from file symlinks
+            #[cfg(windows)]
+            {
+                use std::os::windows::fs::FileTypeExt;
+                if md.file_type().is_symlink_dir() {
+                    return fs::remove_dir(dest);
+                }
+            }
+
+            fs::remove_file(dest)
+        } else {
+            fs::remove_file(dest)
+        }
+    }
+
+    /// Returns `true` if [`Self::dest`] is a regular file with exactly the same contents as
+    /// [`Self::src`]. Replacing it with a link loses nothing, which makes it a trivial conflict.
+    #[must_use]
+    pub fn has_identical_dest(&self) -> bool {
+        let (Ok(dest_md), Ok(src_md)) = (self.dest.symlink_metadata(), self.src.metadata()) else {
+            return false;
+        };
+
+        dest_md.is_file()
+            && src_md.is_file()
+            && dest_md.len() == src_md.len()
+            && has_same_contents(&self.dest, &self.src).unwrap_or(false)
+    }
+
+    /// Returns `true` if [`Self::dest`] is already exactly what unboxing this link would create,
+    /// i.e. a symlink pointing to [`Self::src`] the same way, a hard link to it, or an identical
+    /// copy of it, depending on [`Self::ty`]. Such links are left alone when unboxing.
+    #[must_use]
+    pub fn is_unboxed(&self) -> bool {
+        let Self { src, dest, ty } = self;
+        let Ok(md) = dest.symlink_metadata() else {
+            return false;
+        };
+
+        match ty {
+            LinkType::SymlinkAbsolute => {
+                md.is_symlink() && fs::read_link(dest).is_ok_and(|link_target| link_target == *src)
+            }
+            LinkType::SymlinkRelative => {
+                md.is_symlink()
+                    && fs::read_link(dest)
+                        .is_ok_and(|link_target| link_target == self.get_src_relative_to_dest())
+            }
+            LinkType::HardLink | LinkType::Copy => {
+                // if a parent of `dest` links into the package, `dest` IS the package file
+                let is_package_file = matches!(
+                    (dunce::canonicalize(dest), dunce::canonicalize(src)),
+                    (Ok(dest), Ok(src)) if dest == src
+                );
+                let is_linked = is_same_file(dest, src);
+                md.is_file()
+                    && !is_package_file
+                    && if *ty == LinkType::HardLink {
+                        is_linked
+                    } else {
+                        !is_linked && self.has_identical_dest()
+                    }
+            }
+        }
+    }
+
+    /// Returns `true` if [`Self::dest`] still belongs to `package`, meaning it is either a symlink
+    /// resolving into `package`, a hard link to [`Self::src`], or an unmodified copy of it if
+    /// [`Self::ty`] is [`LinkType::Copy`]. If anything else is at `dest`
+    /// (e.g. a real file that replaced the link, or a link to another package), it isn't ours to
+    /// remove.
+    ///
+    /// # Arguments
+    ///
+    /// - `package` - Package directory `dest` should link into.
+    pub fn is_owned_by<P: AsRef<Path>>(&self, package: P) -> bool {
+        let Self { src, dest, .. } = self;
+        let package = package.as_ref();
+
+        let Ok(md) = dest.symlink_metadata() else {
+            return false;
+        };
+
+        if md.is_symlink() {
+            let canon_package = dunce::canonicalize(package).unwrap_or_else(|_| package.into());
+            // follow the whole chain if possible, otherwise just this link (e.g. it's broken)
+            dunce::canonicalize(dest)
+                .or_else(|_| {
+                    fs::read_link(dest).map(|link_target| {
+                        let dest_parent = dest.parent().unwrap_or(dest);
+                        path_clean::clean(dest_parent.join(link_target))
+                    })
+                })
+                .is_ok_and(|resolved| {
+                    resolved.starts_with(&canon_package) || resolved.starts_with(package)
+                })
+        } else if md.is_file() {
+            // if a parent of `dest` links into the package, `dest` IS the package file
+            let is_package_file = matches!(
+                (dunce::canonicalize(dest), dunce::canonicalize(src)),
+                (Ok(dest), Ok(src)) if dest == src
+            );
+            !is_package_file
+                && (is_same_file(dest, src)
+                    || (self.ty == LinkType::Copy
+                        && fs::metadata(src).is_ok_and(|src_md| md.len() == src_md.len())
+                        && has_same_contents(dest, src).unwrap_or(false)))
+        } else {
+            false
+        }
+    }
+
     /// Utility function that returns a modified [`PlannedLink::src`] that is relative to the
     /// parent of [`PlannedLink::dest`]. Both paths must be absolute before calling this function.
     ///
@@ -186,21 +597,33 @@ impl PlannedLink {
         diff_paths(src, dest_parent).expect("diff_paths should not return None")
     }
 
-    /// Unbox this [`PlannedLink`] by creating either a symbolic link or a hard link, depending on
-    /// [`Self::ty`].
+    /// Unbox this [`PlannedLink`] by creating either a symbolic link, a hard link, or a copy,
+    /// depending on [`Self::ty`].
     ///
     /// # Errors
     ///
     /// An error will be returned if the `dest` parent cannot be created, if [`Self::dest`] is not
-    /// absolute, if [`os_symlink`] fails to create a symbolic link, or if [`fs::hard_link`] fails
-    /// to create a hard link.
+    /// absolute, if [`os_symlink`] fails to create a symbolic link, if [`fs::hard_link`] fails
+    /// to create a hard link, or if [`fs::copy`] fails to create a copy.
     pub fn unbox(&self, create_dirs: bool) -> io::Result<()> {
+        self.unbox_tracking_dirs(create_dirs).map(|_| ())
+    }
+
+    /// Unbox this [`PlannedLink`] like [`PlannedLink::unbox`] and return the directories that
+    /// were created for it, outermost first.
+    ///
+    /// # Errors
+    ///
+    /// See [`PlannedLink::unbox`].
+    pub fn unbox_tracking_dirs(&self, create_dirs: bool) -> io::Result<Vec<PathBuf>> {
         let Self { src, dest, ty } = self;
 
-        if create_dirs {
+        let new_dirs = if create_dirs {
             let target_parent = dest.parent().ok_or(io::ErrorKind::InvalidFilename)?;
-            fs::create_dir_all(target_parent)?;
-        }
+            create_dir_all_tracked(target_parent)?
+        } else {
+            Vec::new()
+        };
 
         match ty {
             LinkType::SymlinkAbsolute => os_symlink(src, dest),
@@ -209,11 +632,57 @@ impl PlannedLink {
                 os_symlink(relative_src, dest)
             }
             LinkType::HardLink => fs::hard_link(src, dest),
-        }
+            LinkType::Copy => fs::copy(src, dest).map(|_| ()),
+        }?;
+
+        Ok(new_dirs)
     }
 }
 
 impl UnboxPlan {
+    /// Get the [`PlannedLink`]s in this plan.
+    #[must_use]
+    pub fn links(&self) -> &[PlannedLink] {
+        &self.links
+    }
+
+    /// Keep only the [`PlannedLink`]s in this plan that `f` returns `true` for.
+    ///
+    /// # Arguments
+    ///
+    /// - `f` - Whether to keep a link.
+    pub(crate) fn retain_links<F: FnMut(&PlannedLink) -> bool>(&mut self, f: F) {
+        self.links.retain(f);
+    }
+
+    /// Get the [`PlannedLink`]s in this plan whose [`PlannedLink::dest`] already exists, which
+    /// are handled according to [`UnboxPlan::efs`] when unboxing. Trivial conflicts (see
+    /// [`UnboxPlan::identical_conflicts`]) aren't included.
+    #[must_use]
+    pub fn conflicts(&self) -> Vec<&PlannedLink> {
+        self.links
+            .iter()
+            .filter(|pl| pl.dest.symlink_metadata().is_ok() && !pl.is_unboxed())
+            .filter(|pl| !(self.replace_identical && pl.has_identical_dest()))
+            .collect()
+    }
+
+    /// Get the [`PlannedLink`]s in this plan whose [`PlannedLink::dest`] is identical to their
+    /// [`PlannedLink::src`] (see [`PlannedLink::has_identical_dest`]), which are replaced
+    /// regardless of [`UnboxPlan::efs`] when unboxing. This is empty if they are treated like
+    /// any other conflict.
+    #[must_use]
+    pub fn identical_conflicts(&self) -> Vec<&PlannedLink> {
+        if !self.replace_identical {
+            return Vec::new();
+        }
+
+        self.links
+            .iter()
+            .filter(|pl| pl.has_identical_dest() && !pl.is_unboxed())
+            .collect()
+    }
+
     /// Returns an object implementing [`Display`] for printing this [`UnboxPlan`] with
     /// supplemental information from a [`PackageConfig`]. This is modeled after
     /// [`std::path::Path::display`].
@@ -254,7 +723,20 @@ impl UnboxPlan {
     ) -> Result<Self, PlanningError> {
         let mut plan = Self {
             links: Vec::new(),
-            efs: cli.existing_file_strategy,
+            // root_config should already be merged with cli, which overrides every rule
+            efs: root_config.existing_file_strategy,
+            efs_rules: if cli.existing_file_strategy.is_some() {
+                Vec::new()
+            } else {
+                root_config.existing_file_rules.clone()
+            },
+            package: root_config.package.clone(),
+            target: root_config.target.clone(),
+            nested_efs: Vec::new(),
+            ask_fallback: cli.ask_fallback,
+            replace_identical: !cli.no_replace_identical,
+            adopt_symlinks: cli.adopt_symlinks,
+            hard_link_fallbacks: Vec::new(),
             #[cfg(debug_assertions)]
             create_dirs: !cli.no_create_dirs,
         };
@@ -269,6 +751,7 @@ impl UnboxPlan {
             return Ok(plan);
         }
         let targets = &mut plan.links;
+        let mut hard_link_fallbacks = Vec::new();
         let mut config_stack = vec![root_config];
         let mut walker = walkdir::WalkDir::new(config_stack[0].package.clone())
             .sort_by_file_name()
@@ -335,6 +818,15 @@ impl UnboxPlan {
                 match PackageConfig::init(entry_path, cli) {
                     Ok(config) => {
                         let link_root = config.link_root;
+                        plan.nested_efs.push(NestedEfs {
+                            package: config.package.clone(),
+                            efs: config.existing_file_strategy,
+                            efs_rules: if cli.existing_file_strategy.is_some() {
+                                Vec::new()
+                            } else {
+                                config.existing_file_rules.clone()
+                            },
+                        });
                         config_stack.push(config);
                         if link_root {
                             walker.skip_current_dir();
@@ -350,30 +842,47 @@ impl UnboxPlan {
             }
 
             // shadow current_config in case a new one was added
+            let current_config = config_stack
+                .last()
+                .expect("config_stack should not be empty");
             let PackageConfig {
                 target,
                 link_type,
+                hard_link_fallback,
                 package: current_package,
                 ..
-            } = config_stack
-                .last()
-                .expect("config_stack should not be empty");
+            } = current_config;
 
-            let dest = if entry_path == current_package {
+            let path_tail = entry_path
+                .strip_prefix(current_package)
+                .expect("entry_path should be prefixed by package");
+            // joining an empty tail would add a trailing slash
+            let dest = if path_tail.as_os_str().is_empty() {
                 target.clone()
             } else {
-                let path_tail = entry_path
-                    .strip_prefix(current_package)
-                    .expect("entry_path should be prefixed by package");
                 target.join(path_tail)
             };
+            // the CLI overrides every rule
+            let mut ty = if cli.link_type.is_some() {
+                *link_type
+            } else {
+                current_config.link_type_for(path_tail)
+            };
+            // directories can't be hard linked at all, so there's nothing to fall back from
+            if ty == LinkType::HardLink && !file_type.is_dir() {
+                ty = __hard_link_type(entry_path, &dest, *hard_link_fallback)?;
+                if ty != LinkType::HardLink {
+                    hard_link_fallbacks.push(dest.clone());
+                }
+            }
 
             targets.push(PlannedLink {
                 src: entry_path.to_path_buf(),
                 dest,
-                ty: *link_type,
+                ty,
             });
         }
+        plan.hard_link_fallbacks = hard_link_fallbacks;
 
         if plan.links.is_empty() {
             Err(PlanningError::EmptyPlan)
@@ -385,31 +894,39 @@ impl UnboxPlan {
     /// Handles a circular reference for a [`PlannedLink`]. This happens when the parent dir of
     /// [`PlannedLink::dest`] is a symlink pointing back into the package.
     ///
-    /// Behavior relies on [`UnboxPlan::efs`]:
+    /// Behavior relies on `efs`:
     /// - [`ExistingFileStrategy::Move`]: rename the problem link to ``problem_link.bak`` and create
     ///   a directory in its place.
+    /// - [`ExistingFileStrategy::Stash`]: move the problem link into this run's backup directory
+    ///   (see [`stash`]) and create a directory in its place.
     /// - [`ExistingFileStrategy::Overwrite`]: overwrite the problem link with a directory of the
     ///   same name.
+    /// - [`ExistingFileStrategy::Trash`]: move the problem link to the trash (see [`trash`]) and
+    ///   create a directory in its place.
     /// - Anything else: exit with error.
     ///
     /// # Arguments
     /// - `problem_link`: Problematic symbolic link in dest dir that points into src package.
     /// - `pl`: The associated [`PlannedLink`]. God's honest truth, this is only used for error
     ///   reporting.
+    /// - `efs`: Strategy to handle the problem link with, which is [`UnboxPlan::efs`] unless the
+    ///   user was asked.
     ///
     /// # Errors
     ///
-    /// An error is returned if [`UnboxPlan::efs`] is not either [`ExistingFileStrategy::Move`] or
-    /// [`ExistingFileStrategy::Overwrite`], if the `problem_link` cannot be renamed/removed, or if
-    /// the new dir cannot be created.
+    /// An error is returned if `efs` is not [`ExistingFileStrategy::Move`],
+    /// [`ExistingFileStrategy::Stash`], [`ExistingFileStrategy::Overwrite`], or
+    /// [`ExistingFileStrategy::Trash`], if the `problem_link` cannot be moved/removed, or if the
+    /// new dir cannot be created.
     fn handle_circular_reference<P: AsRef<Path>>(
         &self,
         problem_link: P,
         pl: &PlannedLink,
+        efs: ExistingFileStrategy,
     ) -> Result<(), UnboxError> {
         let problem_link = problem_link.as_ref();
 
-        match self.efs {
+        match efs {
             ExistingFileStrategy::Move => {
                 let dest_parent_new_name = generate_backup_file_name(problem_link);
                 fs::rename(problem_link, &dest_parent_new_name).map_err(|err| {
@@ -426,6 +943,21 @@ impl UnboxPlan {
                 );
             }
 
+            ExistingFileStrategy::Stash => {
+                let stashed_link =
+                    self.__stash(problem_link)
+                        .map_err(|err| UnboxError::Unboxing {
+                            pl: pl.clone(),
+                            source: err,
+                        })?;
+                eprintln!(
+                    "{}: circular reference stashed: {} -> {}",
+                    "warn".yellow(),
+                    replace_home_with_tilde(problem_link),
+                    replace_home_with_tilde(stashed_link)
+                );
+            }
+
             ExistingFileStrategy::Overwrite => {
                 fs::remove_file(problem_link).map_err(|err| UnboxError::Unboxing {
                     pl: pl.clone(),
@@ -438,11 +970,24 @@ impl UnboxPlan {
                 );
             }
 
+            ExistingFileStrategy::Trash => {
+                let trashed_link = trash(problem_link).map_err(|err| UnboxError::Unboxing {
+                    pl: pl.clone(),
+                    source: err,
+                })?;
+                eprintln!(
+                    "{}: circular reference trashed: {} -> {}",
+                    "warn".yellow(),
+                    replace_home_with_tilde(problem_link),
+                    replace_home_with_tilde(trashed_link)
+                );
+            }
+
             _ => {
                 eprintln!(
                     "{}: circular references cannot be handled by '{}' strategy",
                     "error".bright_red(),
-                    self.efs
+                    efs
                 );
                 return Err(UnboxError::CircularReference {
                     problem_link: problem_link.to_path_buf(),
@@ -466,26 +1011,156 @@ impl UnboxPlan {
     }
 
     /// Unbox the package according to this [`UnboxPlan`], handling any existing target files along
-    /// the way and returning a [`Vec`] of successfully unboxed [`PlannedLink`]s.
+    /// the way and returning a [`Vec`] of successfully unboxed [`PlannedLink`]s. This is a
+    /// convenience wrapper around [`UnboxPlan::unbox_prompted`] that doesn't keep a manifest and
+    /// never asks, so [`ExistingFileStrategy::Ask`] always uses [`UnboxPlan::ask_fallback`].
+    ///
+    /// # Errors
+    ///
+    /// See [`UnboxPlan::unbox_prompted`].
+    pub fn unbox(&self) -> Result<Vec<PlannedLink>, UnboxError> {
+        self.unbox_prompted(&mut UnboxManifest::default(), &mut NoPrompt)
+    }
+
+    /// Get the strategy for an existing [`PlannedLink::dest`] of a link from `src`, which is that
+    /// of the first rule in [`UnboxPlan::efs_rules`] matching `src` relative to the package, or
+    /// [`UnboxPlan::efs`] if none do. If `src` was planned with a nested config, its strategy and
+    /// rules are used instead.
+    ///
+    /// # Arguments
+    ///
+    /// - `src` - Package file of the link.
+    #[must_use]
+    pub fn efs_for(&self, src: &Path) -> ExistingFileStrategy {
+        // the innermost nested config is the one `src` was planned with
+        let (package, efs, efs_rules) = self
+            .nested_efs
+            .iter()
+            .filter(|nested| src.starts_with(&nested.package))
+            .max_by_key(|nested| nested.package.components().count())
+            .map_or((&self.package, self.efs, &self.efs_rules), |nested| {
+                (&nested.package, nested.efs, &nested.efs_rules)
+            });
+        let relative_src = src.strip_prefix(package).unwrap_or(src);
+        efs_rules
+            .iter()
+            .find(|rule| rule.pattern.is_match(&relative_src.to_string_lossy()))
+            .map_or(efs, |rule| rule.strategy)
+    }
+
+    /// Get the strategy for a conflict at `existing` (see [`UnboxPlan::efs_for`]). If it is
+    /// [`ExistingFileStrategy::Ask`], the user is asked through `prompt` unless they already
+    /// answered for all remaining conflicts or can't be asked, in which case
+    /// [`UnboxPlan::ask_fallback`] is used.
+    ///
+    /// # Arguments
+    ///
+    /// - `existing` - Existing file in the way of a link.
+    /// - `src` - Package file the link would point to, for finding the strategy and showing a
+    ///   diff.
+    /// - `prompt` - Where to ask.
+    /// - `remembered` - Answer for all remaining conflicts, if one was given.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the answer cannot be read.
+    fn resolve_efs(
+        &self,
+        existing: &Path,
+        src: &Path,
+        prompt: &mut dyn Prompt,
+        remembered: &mut Option<ExistingFileStrategy>,
+    ) -> io::Result<ExistingFileStrategy> {
+        let efs = self.efs_for(src);
+        if !matches!(efs, ExistingFileStrategy::Ask) {
+            return Ok(efs);
+        }
+        if let Some(efs) = *remembered {
+            return Ok(efs);
+        }
+        if !prompt.is_interactive() {
+            // the CLI rejects falling back to asking, which would never end, but a library caller
+            // could still set it
+            return Ok(match self.ask_fallback {
+                ExistingFileStrategy::Ask => ExistingFileStrategy::ThrowError,
+                efs => efs,
+            });
+        }
+
+        let question = format!(
+            "{} already exists. [a]dopt, [m]ove, [o]verwrite, [i]gnore, show [d]iff, or [q]uit? (uppercase answers all remaining conflicts)",
+            replace_home_with_tilde(existing).cyan()
+        );
+        loop {
+            let answer = prompt.ask(&question)?;
+            let efs = match answer.to_lowercase().as_str() {
+                "a" => ExistingFileStrategy::Adopt,
+                "m" => ExistingFileStrategy::Move,
+                "o" => ExistingFileStrategy::Overwrite,
+                "i" => ExistingFileStrategy::Ignore,
+                "q" => ExistingFileStrategy::ThrowError,
+                "d" => {
+                    prompt.show(&colorize_diff(&conflict_diff(existing, src)));
+                    continue;
+                }
+                _ => {
+                    prompt.show(&format!("unknown answer '{answer}'"));
+                    continue;
+                }
+            };
+
+            if answer.chars().all(char::is_uppercase) {
+                *remembered = Some(efs);
+            }
+            return Ok(efs);
+        }
+    }
+
+    /// Unbox the package according to this [`UnboxPlan`], handling any existing target files along
+    /// the way and returning a [`Vec`] of successfully unboxed [`PlannedLink`]s. Each one is also
+    /// recorded in `manifest` as soon as it is unboxed, so the manifest is accurate even if an
+    /// error occurs partway through. When using [`ExistingFileStrategy::Ask`], the user is asked
+    /// what to do about each existing target file through `prompt`.
+    ///
+    /// # Arguments
+    ///
+    /// - `manifest` - Manifest to record unboxed links in.
+    /// - `prompt` - Where to ask about existing target files.
     ///
     /// # Errors
     ///
     /// An error will be returned if:
     /// - The target file is a symlink, or cannot be copied to the package, or cannot be removed
     ///   after adoption when using [`ExistingFileStrategy::Adopt`].
-    /// - The target file cannot be moved when using [`ExistingFileStrategy::Move`].
+    /// - The target file cannot be moved when using [`ExistingFileStrategy::Move`] or
+    ///   [`ExistingFileStrategy::Stash`].
     /// - The target file cannot be removed when using [`ExistingFileStrategy::Overwrite`],
+    /// - The target file cannot be moved to the trash when using [`ExistingFileStrategy::Trash`],
+    /// - An answer cannot be read from `prompt`.
     /// - [`PlannedLink::unbox`] returns an error.
     ///
     /// # Panics
     ///
     /// This function will panic if a file name cannot be retrieved from a [`PlannedLink`]. Their
     /// `src` and `dest` fields are expected to be absolute paths.
-    pub fn unbox(&self) -> Result<Vec<PlannedLink>, UnboxError> {
+    pub fn unbox_prompted(
+        &self,
+        manifest: &mut UnboxManifest,
+        prompt: &mut dyn Prompt,
+    ) -> Result<Vec<PlannedLink>, UnboxError> {
+        let mut remembered_efs = None;
         let mut unboxed_links = Vec::with_capacity(self.links.capacity());
         for pl in &self.links {
             let PlannedLink { src, dest, .. } = &pl;
 
+            // links that are already correct are left alone so nothing is reported as changed
+            if pl.is_unboxed() {
+                if !manifest.records.iter().any(|r| r.dest == *dest) {
+                    manifest.record(pl, LinkOutcome::Unchanged, None);
+                }
+                continue;
+            }
+
             // if dest is root '/', it will not have a parent, so return itself
             let dest_parent = dest.parent().unwrap_or(dest);
             // If the dest parent is a symlink that points to the current package, that's a
@@ -499,7 +1174,13 @@ impl UnboxPlan {
                     source: err,
                 })? == *src.parent().unwrap_or(src)
             {
-                self.handle_circular_reference(dest_parent, pl)?;
+                let efs = self
+                    .resolve_efs(dest_parent, src, prompt, &mut remembered_efs)
+                    .map_err(|err| UnboxError::Unboxing {
+                        pl: pl.clone(),
+                        source: err,
+                    })?;
+                self.handle_circular_reference(dest_parent, pl, efs)?;
             }
 
             // using `try_exists` follows symlinks; therefore, if a link is invalid (i.e. the link
@@ -516,30 +1197,33 @@ impl UnboxPlan {
                 }
             };
 
-            if dest_exists {
+            let (outcome, backup) = if dest_exists
+                && self.replace_identical
+                && pl.has_identical_dest()
+            {
+                eprintln!(
+                    "{}: replacing {} (identical to the package file)",
+                    "warn".yellow(),
+                    replace_home_with_tilde(dest)
+                );
+                fs::remove_file(dest).map_err(|err| UnboxError::Unboxing {
+                    pl: pl.clone(),
+                    source: err,
+                })?;
+                (LinkOutcome::Replaced, None)
+            } else if dest_exists {
+                let efs = self
+                    .resolve_efs(dest, src, prompt, &mut remembered_efs)
+                    .map_err(|err| UnboxError::Unboxing {
+                        pl: pl.clone(),
+                        source: err,
+                    })?;
                 // TODO: put messages behind --verbose flag (idk how to go about this)
-                match self.efs {
-                    ExistingFileStrategy::Adopt if !dest_is_symlink => {
-                        // If dest is a symlink, it might point to the src file, which means we'd
-                        // be copying a file into itself, thus truncating it. Not ideal.
-                        eprintln!(
-                            "{}: adopting {}",
-                            "warn".yellow(),
-                            replace_home_with_tilde(dest)
-                        );
-                        fs::copy(dest, src).map_err(|err| UnboxError::Unboxing {
-                            pl: pl.clone(),
-                            source: err,
-                        })?;
-                        // remove `dest` so that it can be replaced by a link
-                        fs::remove_file(dest).map_err(|err| UnboxError::Unboxing {
-                            pl: pl.clone(),
-                            source: err,
-                        })?;
+                match efs {
+                    ExistingFileStrategy::Adopt if !dest_is_symlink || self.adopt_symlinks => {
+                        (self.__adopt(pl)?, None)
                     }
                     ExistingFileStrategy::Adopt => {
-                        // TODO: force adopt symlink with CLI flag?
-                        // maybe follow the symlink and adopt that file?
                         return Err(UnboxError::AdoptSymlink(pl.clone()));
                     }
                     ExistingFileStrategy::Ignore => {
@@ -558,10 +1242,24 @@ impl UnboxPlan {
                             replace_home_with_tilde(dest),
                             replace_home_with_tilde(&new_dest)
                         );
-                        fs::rename(dest, new_dest).map_err(|err| UnboxError::Unboxing {
+                        fs::rename(dest, &new_dest).map_err(|err| UnboxError::Unboxing {
+                            pl: pl.clone(),
+                            source: err,
+                        })?;
+                        (LinkOutcome::Moved, Some(new_dest))
+                    }
+                    ExistingFileStrategy::Stash => {
+                        let new_dest = self.__stash(dest).map_err(|err| UnboxError::Unboxing {
                             pl: pl.clone(),
                             source: err,
                         })?;
+                        eprintln!(
+                            "{}: dest exists, stashing {} -> {}",
+                            "warn".yellow(),
+                            replace_home_with_tilde(dest),
+                            replace_home_with_tilde(&new_dest)
+                        );
+                        (LinkOutcome::Moved, Some(new_dest))
                     }
                     ExistingFileStrategy::Overwrite => {
                         eprintln!(
@@ -586,12 +1284,28 @@ impl UnboxPlan {
                                 source: err,
                             })?;
                         }
+                        (LinkOutcome::Overwritten, None)
+                    }
+                    ExistingFileStrategy::Trash => {
+                        let trashed_dest = trash(dest).map_err(|err| UnboxError::Unboxing {
+                            pl: pl.clone(),
+                            source: err,
+                        })?;
+                        eprintln!(
+                            "{}: dest exists, trashing {} -> {}",
+                            "warn".yellow(),
+                            replace_home_with_tilde(dest),
+                            replace_home_with_tilde(trashed_dest)
+                        );
+                        (LinkOutcome::Trashed, None)
                     }
-                    ExistingFileStrategy::ThrowError => {
+                    ExistingFileStrategy::Ask | ExistingFileStrategy::ThrowError => {
                         return Err(UnboxError::TargetAlreadyExists(pl.clone()));
                     }
                 }
-            }
+            } else {
+                (LinkOutcome::Created, None)
+            };
 
             #[cfg(debug_assertions)]
             let create_dirs = self.create_dirs;
@@ -599,26 +1313,326 @@ impl UnboxPlan {
             #[cfg(not(debug_assertions))]
             let create_dirs = true;
 
-            pl.unbox(create_dirs).map_err(|err| UnboxError::Unboxing {
-                pl: pl.clone(),
-                source: err,
-            })?;
+            let new_dirs =
+                pl.unbox_tracking_dirs(create_dirs)
+                    .map_err(|err| UnboxError::Unboxing {
+                        pl: pl.clone(),
+                        source: err,
+                    })?;
 
+            manifest.record_dirs(new_dirs);
+            manifest.record(pl, outcome, backup);
             unboxed_links.push(pl.clone());
         }
 
         unboxed_links.shrink_to_fit();
         Ok(unboxed_links)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::{collections::HashSet, io::Write, iter};
+    /// Get [`UnboxPlan::package`], unless it is blank (i.e. the plan wasn't made by
+    /// [`UnboxPlan::plan_unboxing`]).
+    fn __package(&self) -> Option<&Path> {
+        Some(self.package.as_path()).filter(|p| !p.as_os_str().is_empty())
+    }
+
+    /// Adopt the existing [`PlannedLink::dest`] of `pl` by copying it over [`PlannedLink::src`] and
+    /// removing it so it can be replaced by a link. Directories are merged into `src` (see
+    /// [`__adopt_dir`]). If `dest` is a symlink, the file it points to is adopted instead, unless it
+    /// points into the package, in which case `dest` is just replaced (see [`__adopted_path`]).
+    ///
+    /// Package files that would be overwritten are preserved first: committed files are left to git,
+    /// other files are stashed (see [`stash`]), and files with uncommitted changes are refused.
+    ///
+    /// # Arguments
+    ///
+    /// - `pl` - Link whose `dest` to adopt.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if a package file that would be overwritten has uncommitted changes, if
+    /// `dest` cannot be resolved, copied, or removed, or if it is a directory containing `src`.
+    fn __adopt(&self, pl: &PlannedLink) -> Result<LinkOutcome, UnboxError> {
+        let unboxing_err = |err| UnboxError::Unboxing {
+            pl: pl.clone(),
+            source: err,
+        };
+        let PlannedLink { src, dest, .. } = pl;
+        let package = self.__package();
+        let Some(adopted) = __adopted_path(pl, package).map_err(unboxing_err)? else {
+            eprintln!(
+                "{}: replacing {} (it is already the package file)",
+                "warn".yellow(),
+                replace_home_with_tilde(dest)
+            );
+            fs::remove_file(dest).map_err(unboxing_err)?;
+            return Ok(LinkOutcome::Replaced);
+        };
+
+        // check everything before changing anything
+        let overwritten = __adopted_changes(&adopted, src)
+            .map_err(unboxing_err)?
+            .into_iter()
+            .filter_map(|(path, exists)| exists.then_some(path))
+            .map(|path| {
+                let uncommitted = git_has_uncommitted_changes(&path);
+                (path, uncommitted)
+            })
+            .collect::<Vec<_>>();
+        if let Some((path, _)) = overwritten.iter().find(|(_, u)| *u == Some(true)) {
+            return Err(UnboxError::AdoptUncommitted {
+                pl: pl.clone(),
+                path: path.clone(),
+            });
+        }
+        for (path, _) in overwritten.iter().filter(|(_, u)| u.is_none()) {
+            let backup = stash(path, Path::new(""), package).map_err(unboxing_err)?;
+            eprintln!(
+                "{}: backing up package file {} -> {}",
+                "warn".yellow(),
+                replace_home_with_tilde(path),
+                replace_home_with_tilde(backup)
+            );
+        }
+
+        if adopted == *dest {
+            eprintln!(
+                "{}: adopting {}",
+                "warn".yellow(),
+                replace_home_with_tilde(dest)
+            );
+        } else {
+            eprintln!(
+                "{}: adopting {} (through {})",
+                "warn".yellow(),
+                replace_home_with_tilde(&adopted),
+                replace_home_with_tilde(dest)
+            );
+        }
+        if adopted.is_dir() {
+            __adopt_dir(&adopted, src).map_err(unboxing_err)?;
+        } else {
+            fs::copy(&adopted, src).map_err(unboxing_err)?;
+        }
+        // remove `dest` so that it can be replaced by a link
+        let dest_is_dir = dest.symlink_metadata().map_err(unboxing_err)?.is_dir();
+        if dest_is_dir {
+            fs::remove_dir_all(dest).map_err(unboxing_err)?;
+        } else {
+            fs::remove_file(dest).map_err(unboxing_err)?;
+        }
+        Ok(LinkOutcome::Adopted)
+    }
+
+    /// Move `path` into this run's backup directory (see [`stash`]) with the target and package of
+    /// this plan.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - Path to move.
+    ///
+    /// # Errors
+    ///
+    /// See [`stash`].
+    fn __stash(&self, path: &Path) -> io::Result<PathBuf> {
+        stash(path, &self.target, self.__package())
+    }
+}
+
+/// Utility function for [`UnboxPlan::plan_unboxing`] that gets the type of link to create in place
+/// of a hard link from `src` to `dest`. That's a hard link unless they're on different
+/// filesystems (see [`mount_point`]), in which case `fallback` decides. Filesystems that can't be
+/// determined are assumed to be the same.
+///
+/// # Arguments
+///
+/// - `src` - File in the package.
+/// - `dest` - Where the hard link would be created.
+/// - `fallback` - What to do if `src` and `dest` are on different filesystems.
+///
+/// # Errors
+///
+/// An error is returned if `src` and `dest` are on different filesystems and `fallback` is
+/// [`HardLinkFallback::Error`].
+fn __hard_link_type(
+    src: &Path,
+    dest: &Path,
+    fallback: HardLinkFallback,
+) -> Result<LinkType, PlanningError> {
+    // `dest` itself may be an existing file that's replaced, so only its parent matters
+    let (Some(src_fs), Some(dest_fs)) = (mount_point(src), dest.parent().and_then(mount_point))
+    else {
+        return Ok(LinkType::HardLink);
+    };
+    if src_fs == dest_fs {
+        return Ok(LinkType::HardLink);
+    }
+
+    match fallback {
+        HardLinkFallback::Error => Err(PlanningError::HardLinkAcrossFilesystems {
+            src: src.to_path_buf(),
+            dest: dest.to_path_buf(),
+            src_fs,
+            dest_fs,
+        }),
+        HardLinkFallback::Symlink => Ok(LinkType::SymlinkAbsolute),
+        HardLinkFallback::Copy => Ok(LinkType::Copy),
+    }
+}
+
+/// Get the path that adopting [`PlannedLink::dest`] would copy into the package, which is `dest`
+/// with any symlinks resolved. Returns [`None`] if that's already the package file or, when
+/// `dest` is a symlink, in `package`, since there's nothing to adopt.
+///
+/// # Arguments
+///
+/// - `pl` - Link whose `dest` would be adopted.
+/// - `package` - Package being unboxed, if it is known.
+///
+/// # Errors
+///
+/// An error is returned if `dest` or [`PlannedLink::src`] cannot be resolved.
+fn __adopted_path(pl: &PlannedLink, package: Option<&Path>) -> io::Result<Option<PathBuf>> {
+    let PlannedLink { src, dest, .. } = pl;
+    let adopted = dunce::canonicalize(dest)?;
+    let points_into_package = dest.is_symlink()
+        && package
+            .and_then(|p| dunce::canonicalize(p).ok())
+            .is_some_and(|package| adopted.starts_with(package));
+    // copying a file onto itself truncates it, so never do that
+    if points_into_package || is_same_file(&adopted, src) {
+        Ok(None)
+    } else {
+        Ok(Some(adopted))
+    }
+}
+
+/// Get the package files that adopting `adopted` into `src` would change, along with whether each
+/// one already exists (i.e. it would be overwritten rather than created). Files with the same
+/// contents aren't changed.
+///
+/// # Arguments
+///
+/// - `adopted` - File or directory to adopt (see [`__adopted_path`]).
+/// - `src` - Package file or directory it would be copied to.
+///
+/// # Errors
+///
+/// An error is returned if `adopted` cannot be walked or a file cannot be compared.
+///
+/// # Panics
+///
+/// This function will panic if a walked path isn't in `adopted`, which shouldn't happen.
+fn __adopted_changes(adopted: &Path, src: &Path) -> io::Result<Vec<(PathBuf, bool)>> {
+    let mut changes = Vec::new();
+    for res in walkdir::WalkDir::new(adopted) {
+        let entry = res?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let tail = entry
+            .path()
+            .strip_prefix(adopted)
+            .expect("walked entry should be prefixed by its root");
+        // joining an empty tail would add a trailing slash
+        let entry_src = if tail.as_os_str().is_empty() {
+            src.to_path_buf()
+        } else {
+            src.join(tail)
+        };
+        match entry_src.symlink_metadata() {
+            Ok(md)
+                if md.is_file()
+                    && entry.file_type().is_file()
+                    && has_same_contents(entry.path(), &entry_src)? => {}
+            Ok(_) => changes.push((entry_src, true)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => changes.push((entry_src, false)),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Recursively copy the directory `adopted` into the package directory `src`, merging it with
+/// what's already there. Package files that are overwritten are reported, and permissions are
+/// kept.
+///
+/// # Arguments
+///
+/// - `adopted` - Directory to adopt.
+/// - `src` - Package directory to copy it into.
+///
+/// # Errors
+///
+/// An error is returned if `adopted` contains `src`, if `src` is a file, or if anything cannot be
+/// copied.
+///
+/// # Panics
+///
+/// This function will panic if a walked path isn't in `adopted`, which shouldn't happen.
+fn __adopt_dir(adopted: &Path, src: &Path) -> io::Result<()> {
+    if dunce::canonicalize(src).is_ok_and(|src| src.starts_with(adopted)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot adopt a directory containing the package",
+        ));
+    }
+
+    for res in walkdir::WalkDir::new(adopted) {
+        let entry = res?;
+        let entry_src = src.join(
+            entry
+                .path()
+                .strip_prefix(adopted)
+                .expect("walked entry should be prefixed by its root"),
+        );
+        let file_type = entry.file_type();
+        let existing = entry_src.symlink_metadata().ok();
+        if file_type.is_dir() {
+            match existing {
+                Some(md) if md.is_dir() => {}
+                Some(_) => return Err(io::ErrorKind::NotADirectory.into()),
+                None => fs::create_dir(&entry_src)?,
+            }
+            fs::set_permissions(&entry_src, entry.metadata()?.permissions())?;
+            continue;
+        }
+
+        if let Some(md) = existing {
+            if md.is_dir() {
+                return Err(io::ErrorKind::IsADirectory.into());
+            }
+            if !md.is_symlink() && has_same_contents(entry.path(), &entry_src)? {
+                continue;
+            }
+            eprintln!(
+                "{}: overwriting package file {} with {}",
+                "warn".yellow(),
+                replace_home_with_tilde(&entry_src),
+                replace_home_with_tilde(entry.path())
+            );
+            fs::remove_file(&entry_src)?;
+        }
+        if file_type.is_symlink() {
+            os_symlink(fs::read_link(entry.path())?, &entry_src)?;
+        } else {
+            // this keeps permissions
+            fs::copy(entry.path(), &entry_src)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, io::Write, iter, process};
 
     use anyhow::Context;
     use regex::Regex;
 
+    use crate::package::LinkTypeRule;
+    use crate::prompt::ScriptedPrompt;
     use crate::test_utils::{
         TEST_PACKAGE_FILE_TAILS, TEST_TARGET, make_tmp_tree, make_tmp_tree_with_target,
     };
@@ -1061,8 +2075,12 @@ mod tests {
                 format!("failed to get parent of {}", expected_pl.dest.display())
             })?;
             fs::create_dir_all(parent).context("failed to create test target parent")?;
-            os_symlink(&expected_pl.src, &expected_pl.dest)
-                .context("failed to create test symlink")?;
+            // a link to its own src would already be unboxed, so link to another package file
+            os_symlink(
+                package_path.join(TEST_PACKAGE_FILE_TAILS[1]),
+                &expected_pl.dest,
+            )
+            .context("failed to create test symlink")?;
         }
         let mut expected_plan = TEST_PACKAGE_FILE_TAILS
             .iter()
@@ -1112,6 +2130,243 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_unbox_efs_adopt_through_symlink() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+        let old_checkout = tempfile::tempdir().context("failed to create old checkout")?;
+
+        // one link into an old checkout, one to its own src, and one to another package file
+        let old_file = old_checkout.path().join("old.txt");
+        fs::write(&old_file, "old contents").context("failed to create old file")?;
+        let link_sources = [
+            old_file.clone(),
+            package_path.join(TEST_PACKAGE_FILE_TAILS[2]),
+            package_path.join(TEST_PACKAGE_FILE_TAILS[4]),
+        ];
+        let link_tails = [
+            TEST_PACKAGE_FILE_TAILS[0],
+            TEST_PACKAGE_FILE_TAILS[2],
+            TEST_PACKAGE_FILE_TAILS[3],
+        ];
+        for (link_source, tail) in link_sources.iter().zip(link_tails) {
+            let dest = target_path.join(tail);
+            let parent = dest.parent().context("test dest should have a parent")?;
+            fs::create_dir_all(parent).context("failed to create test target parent")?;
+            os_symlink(link_source, &dest).context("failed to create test symlink")?;
+        }
+
+        let mut plan = TEST_PACKAGE_FILE_TAILS
+            .iter()
+            .map(|tail| PlannedLink {
+                src: package_path.join(tail),
+                dest: target_path.join(tail),
+                ty: LinkType::SymlinkAbsolute,
+            })
+            .collect::<UnboxPlan>();
+        plan.efs = ExistingFileStrategy::Adopt;
+        plan.adopt_symlinks = true;
+        plan.package = package_path.to_path_buf();
+        plan.target = target_path.to_path_buf();
+
+        let mut manifest = UnboxManifest::default();
+        plan.unbox_prompted(&mut manifest, &mut NoPrompt)
+            .context("failed to unbox test package")?;
+
+        let outcome_of = |tail: &str| {
+            manifest
+                .records
+                .iter()
+                .find(|r| r.dest == target_path.join(tail))
+                .map(|r| r.outcome)
+        };
+        assert_eq!(outcome_of(link_tails[0]), Some(LinkOutcome::Adopted));
+        // the link to its own src is already unboxed
+        assert_eq!(outcome_of(link_tails[1]), Some(LinkOutcome::Unchanged));
+        assert_eq!(outcome_of(link_tails[2]), Some(LinkOutcome::Replaced));
+
+        // the old file was adopted and left alone
+        let adopted_src = package_path.join(link_tails[0]);
+        assert_eq!(fs::read_to_string(&adopted_src)?, "old contents");
+        assert_eq!(fs::read_to_string(&old_file)?, "old contents");
+        // package files weren't copied onto each other
+        for tail in &link_tails[1..] {
+            let src = package_path.join(tail);
+            assert_eq!(fs::read_to_string(&src)?, src.to_string_lossy());
+        }
+        for tail in link_tails {
+            let dest = target_path.join(tail);
+            assert_eq!(fs::read_link(&dest)?, package_path.join(tail));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbox_efs_adopt_symlink_into_package() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+
+        // a link to another package file, which must not be copied over this one
+        let tail = TEST_PACKAGE_FILE_TAILS[3];
+        let dest = target_path.join(tail);
+        let parent = dest.parent().context("test dest should have a parent")?;
+        fs::create_dir_all(parent).context("failed to create test target parent")?;
+        os_symlink(package_path.join(TEST_PACKAGE_FILE_TAILS[4]), &dest)
+            .context("failed to create test symlink")?;
+
+        let mut cli = UnboxCli::new(package_path);
+        cli.existing_file_strategy = Some(ExistingFileStrategy::Adopt);
+        cli.adopt_symlinks = true;
+        let config = PackageConfig::init(package_path, &cli)
+            .context("failed to create test package config")?;
+        let plan = UnboxPlan::plan_unboxing(config, &cli)?;
+        plan.unbox().context("failed to unbox test package")?;
+
+        let src = package_path.join(tail);
+        assert_eq!(fs::read_to_string(&src)?, src.to_string_lossy());
+        assert_eq!(fs::read_link(&dest)?, src);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbox_efs_adopt_dir() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+
+        // a real directory is where the folded link would go
+        let src = package_path.join("folder1");
+        let dest = target_path.join("folder1");
+        fs::create_dir_all(dest.join("sub")).context("failed to create test target dir")?;
+        fs::write(dest.join("nested1.txt"), "changed").context("failed to create test file")?;
+        fs::write(dest.join("sub").join("deep.txt"), "deep")
+            .context("failed to create test file")?;
+        let script = dest.join("script.sh");
+        fs::write(&script, "#!/bin/sh").context("failed to create test file")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+                .context("failed to set test file permissions")?;
+        }
+
+        let mut plan = iter::once(PlannedLink {
+            src: src.clone(),
+            dest: dest.clone(),
+            ty: LinkType::SymlinkAbsolute,
+        })
+        .collect::<UnboxPlan>();
+        plan.efs = ExistingFileStrategy::Adopt;
+        plan.unbox().context("failed to unbox test package")?;
+
+        assert_eq!(fs::read_link(&dest)?, src);
+        assert_eq!(fs::read_to_string(src.join("nested1.txt"))?, "changed");
+        assert_eq!(
+            fs::read_to_string(src.join("sub").join("deep.txt"))?,
+            "deep"
+        );
+        // files only in the package are kept
+        let kept = src.join("test_ignore2.txt");
+        assert_eq!(fs::read_to_string(&kept)?, kept.to_string_lossy());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(src.join("script.sh"))?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o755, "permissions were not kept");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbox_efs_adopt_preserves_package_file() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+
+        let test_file_tail = TEST_PACKAGE_FILE_TAILS[0];
+        let src = package_path.join(test_file_tail);
+        let dest = target_path.join(test_file_tail);
+        let original_contents = fs::read_to_string(&src).context("failed to read test src")?;
+        let make_plan = || {
+            let mut plan = iter::once(PlannedLink {
+                src: src.clone(),
+                dest: dest.clone(),
+                ty: LinkType::SymlinkAbsolute,
+            })
+            .collect::<UnboxPlan>();
+            plan.efs = ExistingFileStrategy::Adopt;
+            plan
+        };
+        let write_dest = |contents: &str| -> anyhow::Result<()> {
+            let parent = dest.parent().context("test dest should have a parent")?;
+            fs::create_dir_all(parent).context("failed to create test target parent")?;
+            fs::write(&dest, contents).context("failed to create test target file")
+        };
+        let git = |args: &[&str]| -> anyhow::Result<()> {
+            let status = process::Command::new("git")
+                .arg("-C")
+                .arg(package_path)
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .stdout(process::Stdio::null())
+                .status()
+                .context("failed to run git")?;
+            anyhow::ensure!(status.success(), "git {args:?} failed");
+            Ok(())
+        };
+
+        // outside of git, the original is stashed
+        write_dest("adopted")?;
+        make_plan()
+            .unbox()
+            .context("failed to unbox test package")?;
+        assert_eq!(fs::read_to_string(&src)?, "adopted");
+        let backup = BackupRun::all()?
+            .iter()
+            .flat_map(BackupRun::backups)
+            .find(|b| b.origin == src)
+            .context("package file should be stashed")?;
+        assert_eq!(fs::read_to_string(&backup.path)?, original_contents);
+
+        // uncommitted changes are refused...
+        git(&["init", "-q"])?;
+        git(&["add", "-A"])?;
+        git(&["commit", "-q", "-m", "init"])?;
+        fs::write(&src, "uncommitted").context("failed to change test src")?;
+        fs::remove_file(&dest).context("failed to remove test link")?;
+        write_dest("adopted again")?;
+        let err = make_plan()
+            .unbox()
+            .expect_err("adopted over uncommitted changes");
+        assert!(matches!(err, UnboxError::AdoptUncommitted { path, .. } if path == src));
+        assert_eq!(fs::read_to_string(&src)?, "uncommitted");
+        assert!(!dest.is_symlink(), "{dest:?} was linked");
+
+        // ...and committed files are left to git
+        git(&["commit", "-q", "-am", "change"])?;
+        make_plan()
+            .unbox()
+            .context("failed to unbox test package")?;
+        assert_eq!(fs::read_to_string(&src)?, "adopted again");
+
+        Ok(())
+    }
+
     #[test]
     fn test_unbox_efs_ignore() -> anyhow::Result<()> {
         const EXISTING_TARGET_FILE_CONTENTS: &str = "i already exist";
@@ -1280,53 +2535,313 @@ mod tests {
                     "test src file has unexpected file contents '{src_contents:?}'"
                 );
 
-                let moved_dest_file_name = link
-                    .dest
-                    .file_name()
-                    .expect("test dest path should have a file name")
-                    .to_string_lossy();
-                let moved_dest = link
-                    .dest
-                    .with_file_name(format!("{moved_dest_file_name}.bak0"));
-                assert!(
-                    moved_dest.try_exists().with_context(|| format!(
-                        "failed to verify existence of moved target file {}",
-                        moved_dest.display()
-                    ))?,
-                    "unboxing failed to create moved target file {}",
-                    moved_dest.display()
-                );
+                let moved_dest_file_name = link
+                    .dest
+                    .file_name()
+                    .expect("test dest path should have a file name")
+                    .to_string_lossy();
+                let moved_dest = link
+                    .dest
+                    .with_file_name(format!("{moved_dest_file_name}.bak0"));
+                assert!(
+                    moved_dest.try_exists().with_context(|| format!(
+                        "failed to verify existence of moved target file {}",
+                        moved_dest.display()
+                    ))?,
+                    "unboxing failed to create moved target file {}",
+                    moved_dest.display()
+                );
+
+                let moved_dest_contents = fs::read_to_string(&moved_dest)
+                    .context("failed to read existing target file")?;
+                assert_eq!(
+                    EXISTING_TARGET_FILE_CONTENTS, moved_dest_contents,
+                    "moved target file has unexpected file contents '{moved_dest_contents:?}'"
+                );
+
+                // NOTE: most other tests have a `continue` here; this test does not because the
+                // `dest` link still needs to be checked normally.
+            }
+
+            let PlannedLink { src, dest, .. } = link;
+
+            assert!(
+                dest.try_exists()
+                    .with_context(|| format!("failed to verify existence of {}", dest.display()))?,
+                "{} exists",
+                dest.display()
+            );
+            assert!(dest.is_symlink(), "expected symlink at {}", dest.display());
+            let actual_link_target = fs::read_link(&dest)
+                .with_context(|| format!("failed to read link info for {}", dest.display()))?;
+            assert_eq!(
+                src,
+                actual_link_target,
+                "{} does not point to {}",
+                actual_link_target.display(),
+                src.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbox_efs_stash() -> anyhow::Result<()> {
+        const EXISTING_TARGET_FILE_CONTENTS: &str = "i already exist";
+
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+
+        let test_file_tail = TEST_PACKAGE_FILE_TAILS[0];
+        let existing_dest = target_path.join(test_file_tail);
+        let parent = existing_dest
+            .parent()
+            .context("test dest should have a parent")?;
+        fs::create_dir_all(parent).context("failed to create test target parent")?;
+        fs::write(&existing_dest, EXISTING_TARGET_FILE_CONTENTS)
+            .context("failed to create test target file")?;
+
+        let mut plan = TEST_PACKAGE_FILE_TAILS
+            .iter()
+            .map(|tail| PlannedLink {
+                src: package_path.join(tail),
+                dest: target_path.join(tail),
+                ty: LinkType::SymlinkAbsolute,
+            })
+            .collect::<UnboxPlan>();
+        plan.efs = ExistingFileStrategy::Stash;
+        plan.package = package_path.to_path_buf();
+        plan.target = target_path.to_path_buf();
+        let mut manifest = UnboxManifest::default();
+
+        plan.unbox_prompted(&mut manifest, &mut NoPrompt)
+            .context("failed to unbox test package")?;
+        assert!(
+            existing_dest.is_symlink(),
+            "{existing_dest:?} was not linked"
+        );
+
+        let record = manifest
+            .records
+            .iter()
+            .find(|r| r.dest == existing_dest)
+            .context("stashed link should be recorded")?;
+        assert_eq!(record.outcome, LinkOutcome::Moved);
+        let backup = record
+            .backup
+            .as_ref()
+            .context("stashed file should be recorded")?;
+        // the stashed file keeps its path relative to the target
+        assert!(
+            backup.starts_with(BackupRun::runs_dir()) && backup.ends_with(test_file_tail),
+            "{backup:?} is not in a backup run"
+        );
+        assert_eq!(fs::read_to_string(backup)?, EXISTING_TARGET_FILE_CONTENTS);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbox_efs_trash() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+
+        // a whole directory is in the way
+        let test_file_tail = TEST_PACKAGE_FILE_TAILS[0];
+        let existing_dest = target_path.join(test_file_tail);
+        fs::create_dir_all(&existing_dest).context("failed to create test target dir")?;
+        fs::write(existing_dest.join("keep.txt"), "keep me")
+            .context("failed to create test target file")?;
+
+        let mut plan = TEST_PACKAGE_FILE_TAILS
+            .iter()
+            .map(|tail| PlannedLink {
+                src: package_path.join(tail),
+                dest: target_path.join(tail),
+                ty: LinkType::SymlinkAbsolute,
+            })
+            .collect::<UnboxPlan>();
+        plan.efs = ExistingFileStrategy::Trash;
+
+        let mut manifest = UnboxManifest::default();
+        plan.unbox_prompted(&mut manifest, &mut NoPrompt)
+            .context("failed to unbox test package")?;
+        assert!(
+            existing_dest.is_symlink(),
+            "{existing_dest:?} was not linked"
+        );
+
+        let record = manifest
+            .records
+            .iter()
+            .find(|r| r.dest == existing_dest)
+            .context("trashed link should be recorded")?;
+        assert_eq!(record.outcome, LinkOutcome::Trashed);
+        // the trashed dir is recoverable
+        let trashed_dir = fs::read_dir(TRASH_DIR.join("files"))
+            .context("failed to read test trash")?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .find(|path| path.join("keep.txt").exists())
+            .context("trashed dir should be in the trash")?;
+        assert_eq!(fs::read_to_string(trashed_dir.join("keep.txt"))?, "keep me");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbox_efs_ask() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+
+        let conflict_tails = &TEST_PACKAGE_FILE_TAILS[..3];
+        for tail in conflict_tails {
+            let dest = target_path.join(tail);
+            let parent = dest.parent().context("test dest should have a parent")?;
+            fs::create_dir_all(parent).context("failed to create test target parent")?;
+            fs::write(&dest, "i already exist").context("failed to create test target file")?;
+        }
+
+        let mut plan = TEST_PACKAGE_FILE_TAILS
+            .iter()
+            .map(|tail| PlannedLink {
+                src: package_path.join(tail),
+                dest: target_path.join(tail),
+                ty: LinkType::SymlinkAbsolute,
+            })
+            .collect::<UnboxPlan>();
+        plan.efs = ExistingFileStrategy::Ask;
+
+        // the first conflict is ignored after a bad answer and a diff, the rest are overwritten
+        let mut prompt = ScriptedPrompt::new(&["x", "d", "i", "O"]);
+        let unboxed_links = plan
+            .unbox_prompted(&mut UnboxManifest::default(), &mut prompt)
+            .context("failed to unbox test package")?;
+        assert_eq!(unboxed_links.len(), TEST_PACKAGE_FILE_TAILS.len() - 1);
+        assert!(prompt.shown[0].contains("unknown answer"));
+        assert!(prompt.shown[1].contains("i already exist"));
+
+        let first_dest = target_path.join(conflict_tails[0]);
+        assert!(!first_dest.is_symlink(), "{first_dest:?} was not ignored");
+        for tail in &conflict_tails[1..] {
+            let dest = target_path.join(tail);
+            assert!(dest.is_symlink(), "{dest:?} was not overwritten");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbox_efs_ask_fallback() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+
+        let dest = target_path.join(TEST_PACKAGE_FILE_TAILS[0]);
+        let parent = dest.parent().context("test dest should have a parent")?;
+        fs::create_dir_all(parent).context("failed to create test target parent")?;
+        fs::write(&dest, "i already exist").context("failed to create test target file")?;
+
+        let mut plan = TEST_PACKAGE_FILE_TAILS
+            .iter()
+            .map(|tail| PlannedLink {
+                src: package_path.join(tail),
+                dest: target_path.join(tail),
+                ty: LinkType::SymlinkAbsolute,
+            })
+            .collect::<UnboxPlan>();
+        plan.efs = ExistingFileStrategy::Ask;
+
+        // a prompt without answers can't be asked, so the default fallback throws an error...
+        let err = plan
+            .unbox_prompted(&mut UnboxManifest::default(), &mut ScriptedPrompt::new(&[]))
+            .expect_err("unboxed over an existing file without asking");
+        assert!(matches!(err, UnboxError::TargetAlreadyExists(_)));
+
+        // ...and a configured one is used
+        plan.ask_fallback = ExistingFileStrategy::Move;
+        plan.unbox_prompted(&mut UnboxManifest::default(), &mut ScriptedPrompt::new(&[]))
+            .context("failed to unbox test package")?;
+        assert!(dest.is_symlink(), "{dest:?} was not linked");
+        assert!(
+            dest.with_file_name("nested1.txt.bak0").exists(),
+            "{dest:?} was not moved"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbox_identical_conflict() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+
+        // copy a package file to the target
+        let test_file_tail = TEST_PACKAGE_FILE_TAILS[0];
+        let src = package_path.join(test_file_tail);
+        let dest = target_path.join(test_file_tail);
+        let parent = dest.parent().context("test dest should have a parent")?;
+        fs::create_dir_all(parent).context("failed to create test target parent")?;
+        fs::copy(&src, &dest).context("failed to copy test package file")?;
 
-                let moved_dest_contents = fs::read_to_string(&moved_dest)
-                    .context("failed to read existing target file")?;
-                assert_eq!(
-                    EXISTING_TARGET_FILE_CONTENTS, moved_dest_contents,
-                    "moved target file has unexpected file contents '{moved_dest_contents:?}'"
-                );
+        let plan = TEST_PACKAGE_FILE_TAILS
+            .iter()
+            .map(|tail| PlannedLink {
+                src: package_path.join(tail),
+                dest: target_path.join(tail),
+                ty: LinkType::SymlinkAbsolute,
+            })
+            .collect::<UnboxPlan>();
+        assert!(plan.conflicts().is_empty());
+        assert_eq!(plan.identical_conflicts().len(), 1);
 
-                // NOTE: most other tests have a `continue` here; this test does not because the
-                // `dest` link still needs to be checked normally.
-            }
+        // opting out makes it a real conflict again
+        let mut opted_out_plan = plan
+            .identical_conflicts()
+            .into_iter()
+            .cloned()
+            .collect::<UnboxPlan>();
+        opted_out_plan.replace_identical = false;
+        assert_eq!(opted_out_plan.conflicts().len(), 1);
+        assert!(opted_out_plan.identical_conflicts().is_empty());
+        let err = opted_out_plan
+            .unbox()
+            .expect_err("unboxed over an identical file after opting out");
+        assert!(matches!(err, UnboxError::TargetAlreadyExists(_)));
 
-            let PlannedLink { src, dest, .. } = link;
+        // it's replaced even though the strategy throws an error
+        let mut manifest = UnboxManifest::default();
+        plan.unbox_prompted(&mut manifest, &mut NoPrompt)
+            .context("failed to unbox test package")?;
+        assert!(dest.is_symlink(), "{dest:?} was not replaced");
+        let record = manifest
+            .records
+            .iter()
+            .find(|r| r.dest == dest)
+            .context("replaced link should be recorded")?;
+        assert_eq!(record.outcome, LinkOutcome::Replaced);
 
-            assert!(
-                dest.try_exists()
-                    .with_context(|| format!("failed to verify existence of {}", dest.display()))?,
-                "{} exists",
-                dest.display()
-            );
-            assert!(dest.is_symlink(), "expected symlink at {}", dest.display());
-            let actual_link_target = fs::read_link(&dest)
-                .with_context(|| format!("failed to read link info for {}", dest.display()))?;
-            assert_eq!(
-                src,
-                actual_link_target,
-                "{} does not point to {}",
-                actual_link_target.display(),
-                src.display()
-            );
-        }
+        // a file with different contents is still a conflict
+        fs::remove_file(&dest).context("failed to remove test link")?;
+        fs::write(&dest, "different").context("failed to create test target file")?;
+        opted_out_plan.replace_identical = true;
+        assert!(opted_out_plan.identical_conflicts().is_empty());
+        assert_eq!(opted_out_plan.conflicts().len(), 1);
 
         Ok(())
     }
@@ -1368,17 +2883,33 @@ mod tests {
             })
             .collect::<UnboxPlan>();
         expected_plan.efs = ExistingFileStrategy::Move;
+        // the existing file is identical to the package file, which would otherwise be replaced
+        expected_plan.replace_identical = false;
 
-        // let mut all_unboxed_links = Vec::with_capacity(expected_plan.links.capacity() * 10);
         for i in 0..10 {
+            if i > 0 {
+                // put the existing file back so there's something to move again
+                fs::remove_file(&expected_pl.dest).context("failed to remove test link")?;
+                fs::write(
+                    &expected_pl.dest,
+                    expected_pl.src.as_os_str().as_encoded_bytes(),
+                )
+                .context("failed to create test target file")?;
+            }
             let unboxed_links = expected_plan
                 .unbox()
                 .context("failed to unbox test package")?;
+            // links that are already correct are left alone after the first time
+            let expected_links = if i == 0 {
+                expected_plan.links.clone()
+            } else {
+                vec![expected_pl.clone()]
+            };
             assert_eq!(
                 unboxed_links,
-                expected_plan.links,
+                expected_links,
                 "expected {:?} links, unboxed {:?} links",
-                expected_plan.links.len(),
+                expected_links.len(),
                 unboxed_links.len()
             );
 
@@ -1429,6 +2960,53 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_unbox_skips_unboxed_links() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+
+        for ty in [
+            LinkType::SymlinkAbsolute,
+            LinkType::SymlinkRelative,
+            LinkType::HardLink,
+            LinkType::Copy,
+        ] {
+            let pl = PlannedLink {
+                src: package_path.join(TEST_PACKAGE_FILE_TAILS[4]),
+                dest: target_path.join(TEST_PACKAGE_FILE_TAILS[4]),
+                ty,
+            };
+            let mut plan = iter::once(pl.clone()).collect::<UnboxPlan>();
+            plan.efs = ExistingFileStrategy::Move;
+            plan.replace_identical = false;
+
+            assert_eq!(plan.unbox()?, vec![pl.clone()], "{ty} was not unboxed");
+            assert!(pl.is_unboxed(), "{ty} is not unboxed");
+            assert!(plan.conflicts().is_empty(), "{ty} is a conflict");
+            // unboxing again changes nothing, not even with a strategy that moves existing files
+            let mut manifest = UnboxManifest::default();
+            assert!(
+                plan.unbox_prompted(&mut manifest, &mut NoPrompt)?
+                    .is_empty(),
+                "{ty} was unboxed again"
+            );
+            assert_eq!(manifest.records[0].outcome, LinkOutcome::Unchanged);
+            assert!(
+                !target_path
+                    .join(format!("{}.bak0", TEST_PACKAGE_FILE_TAILS[4]))
+                    .exists(),
+                "{ty} was moved"
+            );
+
+            fs::remove_file(&pl.dest).context("failed to remove test link")?;
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_unbox_efs_move_dir_with_link() -> anyhow::Result<()> {
         const TEST_NESTED_PACKAGE: &str = "folder1";
@@ -2274,6 +3852,172 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_boxup_nested_config_link_root() -> anyhow::Result<()> {
+        const TEST_NESTED_PACKAGE: &str = "folder1";
+
+        let target = tempfile::tempdir().context("failed to create nested test target")?;
+        let expected_target = target.path();
+        let package =
+            make_tmp_tree_with_target(expected_target).context("failed to make test package")?;
+        let package_path = package.path();
+
+        let nested_target = expected_target.join(TEST_NESTED_PACKAGE);
+        let nested_package = package_path.join(TEST_NESTED_PACKAGE);
+        let plan = TEST_PACKAGE_FILE_TAILS
+            .into_iter()
+            .filter(|tail| !tail.starts_with(TEST_NESTED_PACKAGE))
+            .map(|tail| PlannedLink {
+                src: package_path.join(tail),
+                dest: expected_target.join(tail),
+                ty: LinkType::SymlinkAbsolute,
+            })
+            .chain(iter::once(PlannedLink {
+                src: nested_package.clone(),
+                dest: nested_target.clone(),
+                ty: LinkType::SymlinkAbsolute,
+            }))
+            .collect::<UnboxPlan>();
+
+        let links_made = plan.unbox()?;
+        assert!(
+            nested_target.is_dir(),
+            "nested link root was not unboxed to {nested_target:?}"
+        );
+
+        for pl in &links_made {
+            assert!(
+                pl.is_owned_by(package_path),
+                "{:?} does not link into the package",
+                pl.dest
+            );
+            pl.boxup()
+                .with_context(|| format!("failed to box up {:?}", pl.dest))?;
+            assert!(
+                pl.dest.symlink_metadata().is_err(),
+                "{:?} still exists after boxing up",
+                pl.dest
+            );
+        }
+
+        // the package must be left alone, even though a link to it was removed
+        for tail in TEST_PACKAGE_FILE_TAILS {
+            let src = package_path.join(tail);
+            assert!(src.is_file(), "package file {src:?} was removed");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_boxup_relative_dir_symlink() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+
+        // a folded directory, i.e. a relative link to a directory in the package
+        let pl = PlannedLink {
+            src: package_path.join("folder2"),
+            dest: target_path.join("folder2"),
+            ty: LinkType::SymlinkRelative,
+        };
+        pl.unbox(true).context("failed to unbox test dir link")?;
+        assert!(pl.dest.is_dir(), "expected {:?} to point to dir", pl.dest);
+
+        pl.boxup().context("failed to box up test dir link")?;
+        assert!(
+            pl.dest.symlink_metadata().is_err(),
+            "{:?} still exists after boxing up",
+            pl.dest
+        );
+        assert!(
+            pl.src.join("nested2.txt").is_file(),
+            "package dir contents were removed"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_boxup_real_dir() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+
+        let pl = PlannedLink {
+            src: package_path.join("folder1"),
+            dest: target_path.join("folder1"),
+            ty: LinkType::SymlinkAbsolute,
+        };
+        fs::create_dir(&pl.dest).context("failed to create test dir")?;
+
+        let err = pl.boxup().expect_err("boxed up a real directory");
+        assert_eq!(err.kind(), io::ErrorKind::IsADirectory);
+        assert!(pl.dest.is_dir(), "real directory was removed");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_owned_by() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let other_package = make_tmp_tree().context("failed to make other test package")?;
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let target_path = target.path();
+
+        let owned_symlink = PlannedLink {
+            src: package_path.join("test.txt"),
+            dest: target_path.join("test.txt"),
+            ty: LinkType::SymlinkRelative,
+        };
+        let owned_hard_link = PlannedLink {
+            src: package_path.join("test_ignore.txt"),
+            dest: target_path.join("test_ignore.txt"),
+            ty: LinkType::HardLink,
+        };
+        let replaced_link = PlannedLink {
+            src: package_path.join("folder1/nested1.txt"),
+            dest: target_path.join("nested1.txt"),
+            ty: LinkType::SymlinkAbsolute,
+        };
+        let relinked_link = PlannedLink {
+            src: package_path.join("folder2/nested2.txt"),
+            dest: target_path.join("nested2.txt"),
+            ty: LinkType::SymlinkAbsolute,
+        };
+        let missing_link = PlannedLink {
+            src: package_path.join("folder2/nested2 again.txt"),
+            dest: target_path.join("nested2 again.txt"),
+            ty: LinkType::SymlinkAbsolute,
+        };
+
+        owned_symlink
+            .unbox(true)
+            .context("failed to unbox test symlink")?;
+        owned_hard_link
+            .unbox(true)
+            .context("failed to unbox test hard link")?;
+        // the user replaced this link with a real file
+        fs::write(&replaced_link.dest, "not a link").context("failed to write test file")?;
+        // another package re-linked this one
+        os_symlink(
+            other_package.path().join("folder2/nested2.txt"),
+            &relinked_link.dest,
+        )
+        .context("failed to create test symlink")?;
+
+        assert!(owned_symlink.is_owned_by(package_path));
+        assert!(owned_hard_link.is_owned_by(package_path));
+        assert!(!replaced_link.is_owned_by(package_path));
+        assert!(!relinked_link.is_owned_by(package_path));
+        assert!(!missing_link.is_owned_by(package_path));
+
+        Ok(())
+    }
+
     #[cfg(not(windows))]
     #[test]
     fn test_make_relative_dest() {
@@ -2378,6 +4122,206 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_plan_unboxing_efs_rules() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let mut saved_config = PackageConfig::try_from_package(package_path)
+            .context("failed to read test package config")?;
+        saved_config.existing_file_strategy = ExistingFileStrategy::Move;
+        saved_config.existing_file_rules = vec![ExistingFileRule {
+            pattern: Regex::new("^folder1/").context("failed to compile test regex")?,
+            strategy: ExistingFileStrategy::Ignore,
+        }];
+        saved_config
+            .save_to_package()
+            .context("failed to save test package config")?;
+
+        let mut cli = UnboxCli::new(package_path);
+        let config = PackageConfig::init(package_path, &cli)
+            .context("failed to create test package config")?;
+        let plan = UnboxPlan::plan_unboxing(config, &cli)?;
+        let ignored_src = package_path.join(TEST_PACKAGE_FILE_TAILS[0]);
+        let moved_src = package_path.join(TEST_PACKAGE_FILE_TAILS[4]);
+        assert_eq!(plan.efs_for(&ignored_src), ExistingFileStrategy::Ignore);
+        assert_eq!(plan.efs_for(&moved_src), ExistingFileStrategy::Move);
+
+        // the CLI overrides every rule
+        cli.existing_file_strategy = Some(ExistingFileStrategy::Overwrite);
+        let config = PackageConfig::init(package_path, &cli)
+            .context("failed to create test package config")?;
+        let plan = UnboxPlan::plan_unboxing(config, &cli)?;
+        for src in [&ignored_src, &moved_src] {
+            assert_eq!(plan.efs_for(src), ExistingFileStrategy::Overwrite);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_unboxing_nested_efs_rules() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let mut saved_config = PackageConfig::try_from_package(package_path)
+            .context("failed to read test package config")?;
+        saved_config.existing_file_strategy = ExistingFileStrategy::Move;
+        saved_config.existing_file_rules = vec![ExistingFileRule {
+            pattern: Regex::new("^folder1/").context("failed to compile test regex")?,
+            strategy: ExistingFileStrategy::Ignore,
+        }];
+        saved_config
+            .save_to_package()
+            .context("failed to save test package config")?;
+
+        // the nested config's strategy and rules replace the root config's for its files
+        let nested_package = package_path.join("folder1");
+        let mut nested_config = PackageConfig::new_with_target(
+            &nested_package,
+            PathBuf::from(TEST_TARGET).join("folder1"),
+        );
+        nested_config.existing_file_strategy = ExistingFileStrategy::Overwrite;
+        nested_config.existing_file_rules = vec![ExistingFileRule {
+            pattern: Regex::new("^test_ignore").context("failed to compile test regex")?,
+            strategy: ExistingFileStrategy::Trash,
+        }];
+        nested_config
+            .save_to_package()
+            .context("failed to save nested test config")?;
+
+        let mut cli = UnboxCli::new(package_path);
+        let config = PackageConfig::init(package_path, &cli)
+            .context("failed to create test package config")?;
+        let plan = UnboxPlan::plan_unboxing(config, &cli)?;
+        let expected_efs = [
+            (TEST_PACKAGE_FILE_TAILS[0], ExistingFileStrategy::Overwrite),
+            (TEST_PACKAGE_FILE_TAILS[1], ExistingFileStrategy::Trash),
+            (TEST_PACKAGE_FILE_TAILS[2], ExistingFileStrategy::Move),
+            (TEST_PACKAGE_FILE_TAILS[4], ExistingFileStrategy::Move),
+        ];
+        for (tail, efs) in expected_efs {
+            assert_eq!(plan.efs_for(&package_path.join(tail)), efs, "{tail}");
+        }
+
+        // the CLI overrides every rule, nested or not
+        cli.existing_file_strategy = Some(ExistingFileStrategy::Stash);
+        let config = PackageConfig::init(package_path, &cli)
+            .context("failed to create test package config")?;
+        let plan = UnboxPlan::plan_unboxing(config, &cli)?;
+        for (tail, _) in expected_efs {
+            assert_eq!(
+                plan.efs_for(&package_path.join(tail)),
+                ExistingFileStrategy::Stash,
+                "{tail}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_unboxing_link_type_rules() -> anyhow::Result<()> {
+        let package = make_tmp_tree().context("failed to make test package")?;
+        let package_path = package.path();
+        let mut saved_config = PackageConfig::try_from_package(package_path)
+            .context("failed to read test package config")?;
+        saved_config.link_type = LinkType::SymlinkRelative;
+        saved_config.link_type_rules = vec![LinkTypeRule {
+            pattern: Regex::new("^folder2/nested2\\.txt$")
+                .context("failed to compile test regex")?,
+            link_type: LinkType::HardLink,
+        }];
+        saved_config
+            .save_to_package()
+            .context("failed to save test package config")?;
+
+        let mut cli = UnboxCli::new(package_path);
+        let config = PackageConfig::init(package_path, &cli)
+            .context("failed to create test package config")?;
+        let plan = UnboxPlan::plan_unboxing(config, &cli)?;
+        let hard_link_src = package_path.join(TEST_PACKAGE_FILE_TAILS[2]);
+        for pl in plan.links() {
+            let expected_ty = if pl.src == hard_link_src {
+                LinkType::HardLink
+            } else {
+                LinkType::SymlinkRelative
+            };
+            assert_eq!(pl.ty, expected_ty, "unexpected link type for {pl:?}");
+        }
+
+        // the CLI overrides every rule
+        cli.link_type = Some(LinkType::SymlinkAbsolute);
+        let config = PackageConfig::init(package_path, &cli)
+            .context("failed to create test package config")?;
+        let plan = UnboxPlan::plan_unboxing(config, &cli)?;
+        assert!(
+            plan.links()
+                .iter()
+                .all(|pl| pl.ty == LinkType::SymlinkAbsolute)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_unboxing_hard_link_fallback() -> anyhow::Result<()> {
+        // a second filesystem is needed, which is usually a tmpfs here
+        let Some(target) = tempfile::tempdir_in("/dev/shm")
+            .ok()
+            .filter(|target| mount_point(target.path()) != mount_point(std::env::temp_dir()))
+        else {
+            eprintln!("skipping hard link fallback test: no second filesystem to link across");
+            return Ok(());
+        };
+        let target_path = target.path();
+        let package =
+            make_tmp_tree_with_target(target_path).context("failed to make test package")?;
+        let package_path = package.path();
+        let mut saved_config = PackageConfig::try_from_package(package_path)
+            .context("failed to read test package config")?;
+        saved_config.link_type = LinkType::HardLink;
+        saved_config
+            .save_to_package()
+            .context("failed to save test package config")?;
+
+        let mut cli = UnboxCli::new(package_path);
+        let config = PackageConfig::init(package_path, &cli)
+            .context("failed to create test package config")?;
+        let err = UnboxPlan::plan_unboxing(config, &cli).expect_err("planned hard links");
+        assert!(matches!(
+            err,
+            PlanningError::HardLinkAcrossFilesystems { ref dest, .. } if dest.starts_with(target_path)
+        ));
+
+        cli.hard_link_fallback = Some(HardLinkFallback::Symlink);
+        let config = PackageConfig::init(package_path, &cli)
+            .context("failed to create test package config")?;
+        let plan = UnboxPlan::plan_unboxing(config, &cli)?;
+        assert!(
+            plan.links()
+                .iter()
+                .all(|pl| pl.ty == LinkType::SymlinkAbsolute)
+        );
+        assert_eq!(plan.hard_link_fallbacks.len(), plan.links().len());
+
+        cli.hard_link_fallback = Some(HardLinkFallback::Copy);
+        let config = PackageConfig::init(package_path, &cli)
+            .context("failed to create test package config")?;
+        let plan = UnboxPlan::plan_unboxing(config, &cli)?;
+        assert!(plan.links().iter().all(|pl| pl.ty == LinkType::Copy));
+        plan.unbox()?;
+        for pl in plan.links() {
+            assert!(!pl.dest.is_symlink(), "{:?} is not a copy", pl.dest);
+            assert!(pl.is_owned_by(package_path), "{pl:?} is not owned");
+        }
+
+        // modified copies aren't ours to remove anymore
+        let modified = &plan.links()[0];
+        fs::write(&modified.dest, "modified").context("failed to modify test copy")?;
+        assert!(!modified.is_owned_by(package_path));
+
+        Ok(())
+    }
+
     #[test]
     fn test_plan_unboxing_nested_config() -> anyhow::Result<()> {
         const TEST_NESTED_PACKAGE: &str = "folder1/";
@@ -0,0 +1,160 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+#[cfg(test)]
+use std::collections::VecDeque;
+
+/// Asks the user questions and shows them messages. Interactive unboxing goes through this so it
+/// can be scripted in tests.
+pub trait Prompt {
+    /// Returns `true` if questions can be answered. If not, callers should fall back to a
+    /// non-interactive choice instead of asking.
+    fn is_interactive(&self) -> bool;
+
+    /// Ask a question and return the answer with surrounding whitespace trimmed.
+    ///
+    /// # Arguments
+    ///
+    /// - `question` - Question to ask.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the answer cannot be read, including when there are no answers
+    /// left to read.
+    fn ask(&mut self, question: &str) -> io::Result<String>;
+
+    /// Show a message.
+    ///
+    /// # Arguments
+    ///
+    /// - `message` - Message to show.
+    fn show(&mut self, message: &str);
+}
+
+/// A [`Prompt`] that asks on stdout and reads answers from stdin. It is only interactive if stdin
+/// is a terminal.
+#[derive(Debug, Default)]
+pub struct TerminalPrompt;
+
+impl Prompt for TerminalPrompt {
+    fn is_interactive(&self) -> bool {
+        io::stdin().is_terminal()
+    }
+
+    fn ask(&mut self, question: &str) -> io::Result<String> {
+        print!("{question} ");
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(answer.trim().to_string())
+    }
+
+    fn show(&mut self, message: &str) {
+        println!("{message}");
+    }
+}
+
+/// A [`Prompt`] that never asks, so callers always fall back to their non-interactive choice.
+/// Messages are shown on stderr.
+#[derive(Debug, Default)]
+pub struct NoPrompt;
+
+impl Prompt for NoPrompt {
+    fn is_interactive(&self) -> bool {
+        false
+    }
+
+    fn ask(&mut self, _question: &str) -> io::Result<String> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn show(&mut self, message: &str) {
+        eprintln!("{message}");
+    }
+}
+
+/// Ask a yes/no question through `prompt`. The default answer is no.
+///
+/// # Arguments
+///
+/// - `prompt` - Prompt to ask through.
+/// - `question` - Question to ask.
+///
+/// # Errors
+///
+/// An error is returned if `prompt` is not interactive or the answer cannot be read.
+pub fn confirm(prompt: &mut dyn Prompt, question: &str) -> io::Result<bool> {
+    if !prompt.is_interactive() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "cannot ask for confirmation without a terminal",
+        ));
+    }
+
+    let answer = prompt.ask(&format!("{question} [y/N]"))?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// **_Test_** [`Prompt`] that answers with a script and keeps everything it is shown.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct ScriptedPrompt {
+    /// Answers to give, in order.
+    answers: VecDeque<String>,
+    /// Messages that were shown.
+    pub shown: Vec<String>,
+}
+
+#[cfg(test)]
+impl ScriptedPrompt {
+    /// Create a [`ScriptedPrompt`] that gives `answers` in order.
+    ///
+    /// # Arguments
+    ///
+    /// - `answers` - Answers to give.
+    pub fn new(answers: &[&str]) -> Self {
+        Self {
+            answers: answers.iter().map(ToString::to_string).collect(),
+            shown: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Prompt for ScriptedPrompt {
+    fn is_interactive(&self) -> bool {
+        !self.answers.is_empty()
+    }
+
+    fn ask(&mut self, _question: &str) -> io::Result<String> {
+        self.answers
+            .pop_front()
+            .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+    }
+
+    fn show(&mut self, message: &str) {
+        self.shown.push(message.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confirm() -> io::Result<()> {
+        let mut prompt = ScriptedPrompt::new(&["Y", "yes", "", "nope"]);
+        assert!(confirm(&mut prompt, "Continue?")?);
+        assert!(confirm(&mut prompt, "Continue?")?);
+        assert!(!confirm(&mut prompt, "Continue?")?);
+        assert!(!confirm(&mut prompt, "Continue?")?);
+
+        let err = confirm(&mut prompt, "Continue?").expect_err("confirmed without answers");
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(confirm(&mut NoPrompt, "Continue?").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    constants::TRASH_DIR,
+    utils::{move_path, unix_timestamp},
+};
+
+/// Move `path` to the trash (see [`TRASH_DIR`]) following the
+/// [freedesktop trash specification](https://specifications.freedesktop.org/trash-spec/latest/),
+/// so it can be recovered with desktop tools. Returns where `path` was moved to.
+///
+/// # Arguments
+///
+/// - `path` - Absolute path to move to the trash.
+///
+/// # Errors
+///
+/// An error is returned if `path` isn't absolute or doesn't have a file name, or if the trash
+/// cannot be written to.
+pub fn trash<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    let path = path.as_ref();
+    if !path.is_absolute() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only absolute paths can be trashed",
+        ));
+    }
+    let file_name = path
+        .file_name()
+        .ok_or(io::ErrorKind::InvalidFilename)?
+        .to_string_lossy();
+
+    let files_dir = TRASH_DIR.join("files");
+    let info_dir = TRASH_DIR.join("info");
+    fs::create_dir_all(&files_dir)?;
+    fs::create_dir_all(&info_dir)?;
+
+    // creating the info file first reserves the name, as the spec requires
+    let mut i = 1_usize;
+    let (trashed_path, info_path, mut info_file) = loop {
+        let name = if i == 1 {
+            file_name.to_string()
+        } else {
+            format!("{file_name}.{i}")
+        };
+        i += 1;
+
+        let trashed_path = files_dir.join(&name);
+        if trashed_path.symlink_metadata().is_ok() {
+            continue;
+        }
+        let info_path = info_dir.join(format!("{name}.trashinfo"));
+        match fs::File::create_new(&info_path) {
+            Ok(info_file) => break (trashed_path, info_path, info_file),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    };
+
+    let info = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        __encode_trash_path(path),
+        __format_deletion_date(unix_timestamp())
+    );
+    info_file
+        .write_all(info.as_bytes())
+        .and_then(|()| move_path(path, &trashed_path))
+        .inspect_err(|_| {
+            // don't leave an info file without a file in the trash
+            let _ = fs::remove_file(&info_path);
+        })?;
+
+    Ok(trashed_path)
+}
+
+/// Percent-encode a path for the `Path` key of a `.trashinfo` file. Everything but unreserved
+/// characters and `/` is encoded.
+///
+/// # Arguments
+///
+/// - `path` - Path to encode.
+fn __encode_trash_path(path: &Path) -> String {
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec();
+
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy().as_bytes().to_vec();
+
+    bytes.iter().fold(String::new(), |mut encoded, &b| {
+        if b.is_ascii_alphanumeric() || b"-_.~/".contains(&b) {
+            encoded.push(char::from(b));
+        } else {
+            let _ = write!(encoded, "%{b:02X}");
+        }
+        encoded
+    })
+}
+
+/// Format a Unix timestamp for the `DeletionDate` key of a `.trashinfo` file
+/// (`YYYY-MM-DDThh:mm:ss`). The spec asks for local time, but there's no time zone database to
+/// convert with, so this is UTC.
+///
+/// # Arguments
+///
+/// - `timestamp` - Seconds since the Unix epoch.
+fn __format_deletion_date(timestamp: u64) -> String {
+    let (days, day_secs) = (timestamp / 86400, timestamp % 86400);
+
+    // Howard Hinnant's `civil_from_days`, shifted so eras start on March 1st, 0000
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+        day_secs / 3600,
+        day_secs % 3600 / 60,
+        day_secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn test_trash() -> anyhow::Result<()> {
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let file = target.path().join("trash me.txt");
+
+        let mut trashed_paths = Vec::new();
+        for contents in ["first", "second"] {
+            fs::write(&file, contents).context("failed to write test file")?;
+            let trashed_path = trash(&file).context("failed to trash test file")?;
+            assert!(!file.exists(), "{file:?} was not moved");
+            assert_eq!(fs::read_to_string(&trashed_path)?, contents);
+            trashed_paths.push(trashed_path);
+        }
+        // trashing the same name twice doesn't overwrite the first one
+        assert_ne!(trashed_paths[0], trashed_paths[1]);
+
+        let trashed_name = trashed_paths[1]
+            .file_name()
+            .context("trashed path should have a file name")?
+            .to_string_lossy();
+        let info_path = TRASH_DIR
+            .join("info")
+            .join(format!("{trashed_name}.trashinfo"));
+        let info = fs::read_to_string(&info_path).context("failed to read trash info")?;
+        let encoded_path = __encode_trash_path(&file);
+        assert!(encoded_path.ends_with("/trash%20me.txt"));
+        assert!(info.starts_with(&format!("[Trash Info]\nPath={encoded_path}\nDeletionDate=")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_deletion_date() {
+        assert_eq!(__format_deletion_date(0), "1970-01-01T00:00:00");
+        assert_eq!(__format_deletion_date(951_827_696), "2000-02-29T12:34:56");
+        assert_eq!(__format_deletion_date(1_735_689_599), "2024-12-31T23:59:59");
+    }
+}
@@ -1,8 +1,10 @@
 use std::{
     ffi::OsString,
-    io,
+    fs,
+    io::{self, BufRead},
     path::{Path, PathBuf},
     process,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Context;
@@ -88,6 +90,207 @@ pub fn generate_backup_file_name<P: Into<PathBuf>>(p: P) -> PathBuf {
     panic!("why do you have {max} versions!?");
 }
 
+/// Recursively create a directory and all of its missing parents, like [`fs::create_dir_all`],
+/// and return the directories that were created, outermost first.
+///
+/// # Arguments
+///
+/// - `path` - Directory to create.
+///
+/// # Errors
+///
+/// An error is returned if [`fs::create_dir_all`] fails.
+pub fn create_dir_all_tracked<P: AsRef<Path>>(path: P) -> io::Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let mut created_dirs = path
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.try_exists().unwrap_or(true))
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    created_dirs.reverse();
+
+    fs::create_dir_all(path)?;
+    Ok(created_dirs)
+}
+
+/// Returns `true` if the files at `a` and `b` have exactly the same contents. They are compared
+/// in chunks, so big files are never read into memory all at once.
+///
+/// # Arguments
+///
+/// - `a` - First file.
+/// - `b` - Second file.
+///
+/// # Errors
+///
+/// An error is returned if either file cannot be read.
+pub fn has_same_contents<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> io::Result<bool> {
+    let mut a_reader = io::BufReader::new(fs::File::open(a)?);
+    let mut b_reader = io::BufReader::new(fs::File::open(b)?);
+    loop {
+        let a_buf = a_reader.fill_buf()?;
+        let b_buf = b_reader.fill_buf()?;
+        let len = a_buf.len().min(b_buf.len());
+        if len == 0 {
+            return Ok(a_buf.is_empty() && b_buf.is_empty());
+        }
+        if a_buf[..len] != b_buf[..len] {
+            return Ok(false);
+        }
+
+        a_reader.consume(len);
+        b_reader.consume(len);
+    }
+}
+
+/// Move a file, link, or directory from `from` to `to`. This is [`fs::rename`], except moving
+/// across file systems falls back to copying `from` and removing it. Links are moved as links.
+///
+/// # Arguments
+///
+/// - `from` - Path to move.
+/// - `to` - Where to move it. Its parent must exist.
+///
+/// # Errors
+///
+/// An error is returned if `from` cannot be renamed, copied, or removed.
+///
+/// # Panics
+///
+/// This function will panic if a walked path isn't in `from`, which shouldn't happen.
+pub fn move_path<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let from = from.as_ref();
+    let to = to.as_ref();
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {}
+        res => return res,
+    }
+
+    for res in walkdir::WalkDir::new(from) {
+        let entry = res?;
+        let entry_to = to.join(
+            entry
+                .path()
+                .strip_prefix(from)
+                .expect("walked entry should be prefixed by its root"),
+        );
+        let file_type = entry.file_type();
+        if file_type.is_symlink() {
+            os_symlink(fs::read_link(entry.path())?, &entry_to)?;
+        } else if file_type.is_dir() {
+            fs::create_dir(&entry_to)?;
+        } else {
+            fs::copy(entry.path(), &entry_to)?;
+        }
+    }
+
+    if from.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    }
+}
+
+/// Atomically write `contents` to `path` by writing to a temporary file next to it and renaming
+/// the temporary file over `path`.
+///
+/// # Arguments
+///
+/// - `path` - File to write.
+/// - `contents` - Contents to write.
+///
+/// # Errors
+///
+/// An error is returned if the temporary file cannot be written or renamed.
+pub fn atomic_write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    let path = path.as_ref();
+    let file_name = path.file_name().ok_or(io::ErrorKind::InvalidFilename)?;
+    let mut tmp_name = file_name.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        // don't leave the temp file lying around
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+/// Get the current git revision (`HEAD`) of the repository containing `path`. Returns [`None`] if
+/// `path` is not in a git repository or `git` cannot be run.
+///
+/// # Arguments
+///
+/// - `path` - Path in a git repository.
+pub fn git_revision<P: AsRef<Path>>(path: P) -> Option<String> {
+    let output = process::Command::new("git")
+        .arg("-C")
+        .arg(path.as_ref())
+        .args(["rev-parse", "HEAD"])
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+/// Check whether the file at `path` has uncommitted changes in its git repository. Returns
+/// [`None`] if it isn't tracked by git (e.g. it is untracked, ignored, or not in a repository) or
+/// `git` cannot be run.
+///
+/// # Arguments
+///
+/// - `path` - File to check.
+pub fn git_has_uncommitted_changes<P: AsRef<Path>>(path: P) -> Option<bool> {
+    let path = path.as_ref();
+    let output = process::Command::new("git")
+        .arg("-C")
+        .arg(path.parent()?)
+        .args(["status", "--porcelain", "--ignored", "--"])
+        .arg(path.file_name()?)
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+    match output.stdout.get(..2) {
+        None => Some(false),
+        Some(b"??" | b"!!") => None,
+        Some(_) => Some(true),
+    }
+}
+
+/// Hash `bytes` with 64-bit FNV-1a. This is used for state registry keys and file summaries
+/// because, unlike [`std::hash::DefaultHasher`], it is guaranteed to be stable across Rust
+/// versions.
+///
+/// # Arguments
+///
+/// - `bytes` - Bytes to hash.
+#[must_use]
+pub fn fnv1a_64(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(PRIME)
+    })
+}
+
+/// Get the current time as seconds since the Unix epoch.
+#[must_use]
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
 /// If the [`Path`] reference begins with the users home directory, it is replaced with a `~`. This
 /// is kinda the opposite of [`expand_into_pathbuf`] and meant for printing.
 ///
@@ -175,3 +378,90 @@ where
         unimplemented!()
     }
 }
+
+/// Returns `true` if the paths `a` and `b` name the same file, i.e. they are hard links to each
+/// other. Symlinks are followed. On Unix, this compares the device and inode numbers; on Windows,
+/// the volume serial number and file index. Elsewhere, files can't be identified, so this always
+/// returns `false`. Returns `false` if either file can't be read.
+///
+/// # Arguments
+///
+/// - `a` - Path of the first file.
+/// - `b` - Path of the second file.
+#[must_use]
+pub fn is_same_file<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        match (fs::metadata(a), fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::io::AsRawHandle;
+
+        use windows_sys::Win32::Storage::FileSystem::{
+            BY_HANDLE_FILE_INFORMATION, GetFileInformationByHandle,
+        };
+
+        /// Get the volume serial number and file index of the file at `path`.
+        fn file_id(path: &Path) -> Option<(u32, u64)> {
+            let file = fs::File::open(path).ok()?;
+            let mut info = BY_HANDLE_FILE_INFORMATION::default();
+            // SAFETY: the handle is open for as long as `file` lives and `info` is a valid,
+            // writable BY_HANDLE_FILE_INFORMATION
+            let ok = unsafe { GetFileInformationByHandle(file.as_raw_handle(), &raw mut info) };
+            (ok != 0).then(|| {
+                (
+                    info.dwVolumeSerialNumber,
+                    (u64::from(info.nFileIndexHigh) << 32) | u64::from(info.nFileIndexLow),
+                )
+            })
+        }
+
+        matches!((file_id(a.as_ref()), file_id(b.as_ref())), (Some(a), Some(b)) if a == b)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (a, b);
+        false
+    }
+}
+
+/// Get the mount point of the filesystem `path` is on, i.e. its outermost ancestor on the same
+/// device. If `path` doesn't exist, its closest existing ancestor is used, since that's where it
+/// would be created. Symlinks are followed. Returns [`None`] if no ancestor of `path` can be read,
+/// or on platforms other than Unix.
+///
+/// # Arguments
+///
+/// - `path` - Path to find the filesystem of.
+#[must_use]
+pub fn mount_point<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let existing = path
+            .as_ref()
+            .ancestors()
+            .find_map(|p| dunce::canonicalize(p).ok())?;
+        let dev = fs::metadata(&existing).ok()?.dev();
+        existing
+            .ancestors()
+            .take_while(|p| fs::metadata(p).is_ok_and(|md| md.dev() == dev))
+            .last()
+            .map(Path::to_path_buf)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
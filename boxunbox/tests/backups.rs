@@ -0,0 +1,43 @@
+use std::fs;
+
+use anyhow::Context;
+
+use common::{TestPackage, exists};
+
+mod common;
+
+#[test]
+fn test_backups_prune() -> anyhow::Result<()> {
+    let package = TestPackage::new("")?;
+    let target = package.target.path();
+    fs::write(package.dest(), "mine").context("failed to write existing file")?;
+    let output = package.run(env!("CARGO_BIN_EXE_unbox"), &["--if-exists", "move"])?;
+    assert!(output.status.success(), "unbox failed: {output:?}");
+    let recorded = target.join("test.txt.bak0");
+    assert!(exists(&recorded), "existing file was not moved");
+    // named like a backup, but boxunbox didn't make it
+    let unrecorded = target.join("foo.bak1");
+    fs::write(&unrecorded, "not a backup").context("failed to write unrecorded file")?;
+
+    let prune = |args: &[&str]| {
+        package
+            .command(env!("CARGO_BIN_EXE_unbox"))
+            .args(["backups", "prune", "--keep", "0", "--target"])
+            .arg(target)
+            .args(args)
+            .output()
+            .context("failed to run unbox backups prune")
+    };
+
+    // deleting needs confirmation, which can't be given without a terminal
+    let output = prune(&[])?;
+    assert!(!output.status.success(), "prune didn't ask: {output:?}");
+    assert!(exists(&recorded), "backup was deleted without confirmation");
+
+    let output = prune(&["--yes"])?;
+    assert!(output.status.success(), "prune failed: {output:?}");
+    assert!(!exists(&recorded), "recorded backup was not pruned");
+    assert!(exists(&unrecorded), "unrecorded file was pruned");
+
+    Ok(())
+}
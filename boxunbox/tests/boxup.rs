@@ -0,0 +1,120 @@
+use std::{fs, process::Command};
+
+use anyhow::Context;
+
+use common::{TestPackage, exists};
+
+mod common;
+
+#[test]
+fn test_boxup_manifest() -> anyhow::Result<()> {
+    let package = TestPackage::new("")?;
+    package.unbox()?;
+    assert_eq!(package.manifest_count(), 1);
+
+    let output = package.boxup(&[])?;
+    assert!(output.status.success(), "boxup failed: {output:?}");
+    assert!(!exists(&package.dest()), "link was not removed");
+    assert_eq!(package.manifest_count(), 0, "manifest was not removed");
+
+    Ok(())
+}
+
+#[test]
+fn test_boxup_scan() -> anyhow::Result<()> {
+    let package = TestPackage::new("")?;
+    package.unbox()?;
+    fs::remove_dir_all(package.manifests_dir()).context("failed to remove manifests")?;
+
+    // scanned links need confirmation, which can't be given without a terminal
+    let output = package.boxup(&[])?;
+    assert!(!output.status.success(), "boxup didn't ask: {output:?}");
+    assert!(
+        exists(&package.dest()),
+        "link was removed without confirmation"
+    );
+
+    let output = package.boxup(&["--yes"])?;
+    assert!(output.status.success(), "boxup failed: {output:?}");
+    assert!(!exists(&package.dest()), "link was not removed");
+
+    Ok(())
+}
+
+#[test]
+fn test_boxup_force() -> anyhow::Result<()> {
+    let package = TestPackage::new("")?;
+    package.unbox()?;
+    // the user replaced the link with their own file
+    fs::remove_file(package.dest()).context("failed to remove link")?;
+    fs::write(package.dest(), "mine").context("failed to replace link")?;
+
+    let output = package.boxup(&[])?;
+    assert!(output.status.success(), "boxup failed: {output:?}");
+    assert_eq!(fs::read_to_string(package.dest())?, "mine");
+
+    let output = package.boxup(&["--force"])?;
+    assert!(output.status.success(), "boxup failed: {output:?}");
+    assert!(
+        !exists(&package.dest()),
+        "file was not removed with --force"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_boxup_failing_post_hook() -> anyhow::Result<()> {
+    let package = TestPackage::new("[hooks]\npost_boxup = [\"exit 1\"]\n")?;
+    package.unbox()?;
+
+    let output = package.boxup(&[])?;
+    assert!(!output.status.success(), "failing hook was ignored");
+    assert!(!exists(&package.dest()), "link was not removed");
+    // the manifest is updated before the hook runs
+    assert_eq!(package.manifest_count(), 0, "manifest is stale");
+
+    Ok(())
+}
+
+#[test]
+fn test_boxup_required_package() -> anyhow::Result<()> {
+    let packages = tempfile::tempdir().context("failed to create temp packages dir")?;
+    let target = tempfile::tempdir().context("failed to create temp target")?;
+    let state = tempfile::tempdir().context("failed to create temp state dir")?;
+    // b requires a, c is unrelated
+    for (name, extra_config) in [("a", ""), ("b", "requires = [\"../a\"]\n"), ("c", "")] {
+        let package = packages.path().join(name);
+        fs::create_dir(&package).context("failed to create test package")?;
+        fs::write(package.join(format!("{name}.txt")), name)
+            .context("failed to write test file")?;
+        let config = format!("target = {:?}\n{extra_config}", target.path());
+        fs::write(package.join(".bub.toml"), config).context("failed to write test config")?;
+    }
+    let run = |bin: &str, args: &[&str]| {
+        Command::new(bin)
+            .args(args)
+            .env("XDG_STATE_HOME", state.path())
+            .current_dir(packages.path())
+            .output()
+            .with_context(|| format!("failed to run {bin}"))
+    };
+
+    let output = run(env!("CARGO_BIN_EXE_unbox"), &["a", "b", "c"])?;
+    assert!(output.status.success(), "unbox failed: {output:?}");
+
+    // a dry run reports the problem without stopping
+    let output = run(env!("CARGO_BIN_EXE_boxup"), &["--dry-run", "c", "a"])?;
+    assert!(output.status.success(), "dry run failed: {output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is required by"));
+
+    // nothing is boxed up, not even the packages before the required one
+    let output = run(env!("CARGO_BIN_EXE_boxup"), &["c", "a"])?;
+    assert!(!output.status.success(), "required package was boxed up");
+    for name in ["a", "c"] {
+        let dest = target.path().join(format!("{name}.txt"));
+        assert!(exists(&dest), "{dest:?} was removed");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! Helpers shared by the integration tests. Not every test uses every helper.
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use anyhow::Context;
+
+/// A package with one file, `test.txt`, that unboxes to its own temporary target. Every command is
+/// run with its own state directory so the real state is never touched.
+pub struct TestPackage {
+    pub package: tempfile::TempDir,
+    pub target: tempfile::TempDir,
+    pub state: tempfile::TempDir,
+}
+
+impl TestPackage {
+    /// Create a [`TestPackage`] whose config has `extra_config` appended to it.
+    ///
+    /// # Arguments
+    ///
+    /// - `extra_config` - TOML to append to the package config.
+    pub fn new(extra_config: &str) -> anyhow::Result<Self> {
+        let package = tempfile::tempdir().context("failed to create temp package")?;
+        let target = tempfile::tempdir().context("failed to create temp target")?;
+        let state = tempfile::tempdir().context("failed to create temp state dir")?;
+        fs::write(package.path().join("test.txt"), "test").context("failed to write test file")?;
+        let config = format!("target = {:?}\n{extra_config}", target.path());
+        fs::write(package.path().join(".bub.toml"), config)
+            .context("failed to write test config")?;
+
+        Ok(Self {
+            package,
+            target,
+            state,
+        })
+    }
+
+    pub fn dest(&self) -> PathBuf {
+        self.target.path().join("test.txt")
+    }
+
+    pub fn manifests_dir(&self) -> PathBuf {
+        self.state.path().join("boxunbox").join("manifests")
+    }
+
+    /// Count the manifests in the state registry.
+    pub fn manifest_count(&self) -> usize {
+        fs::read_dir(self.manifests_dir()).map_or(0, Iterator::count)
+    }
+
+    /// Run the binary at `bin` on this package with `args`.
+    ///
+    /// # Arguments
+    ///
+    /// - `bin` - Path of the binary to run.
+    /// - `args` - Arguments to pass before the package.
+    pub fn run(&self, bin: &str, args: &[&str]) -> anyhow::Result<Output> {
+        self.command(bin)
+            .args(args)
+            .arg(self.package.path())
+            .output()
+            .with_context(|| format!("failed to run {bin}"))
+    }
+
+    /// Get a [`Command`] that runs the binary at `bin` in this package with this package's state
+    /// directory.
+    ///
+    /// # Arguments
+    ///
+    /// - `bin` - Path of the binary to run.
+    pub fn command(&self, bin: &str) -> Command {
+        let mut command = Command::new(bin);
+        command
+            .env("XDG_STATE_HOME", self.state.path())
+            .current_dir(self.package.path());
+        command
+    }
+
+    pub fn unbox(&self) -> anyhow::Result<()> {
+        let output = self.run(env!("CARGO_BIN_EXE_unbox"), &[])?;
+        anyhow::ensure!(output.status.success(), "unbox failed: {output:?}");
+        anyhow::ensure!(
+            self.dest().is_symlink(),
+            "unbox didn't link {:?}",
+            self.dest()
+        );
+        Ok(())
+    }
+
+    pub fn boxup(&self, args: &[&str]) -> anyhow::Result<Output> {
+        self.run(env!("CARGO_BIN_EXE_boxup"), args)
+    }
+}
+
+/// Returns `true` if `path` exists without following symlinks.
+pub fn exists(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}